@@ -1,39 +1,250 @@
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{self, BufRead, BufReader};
 use regex::Regex;
 
-fn parse_line(line: &str) -> i32 {
-    let re = Regex::new(r"(?<sign>[LR])(?<value>\d+)").unwrap();
-    let Some(caps) = re.captures(line) else {
-        panic!("Line ({}) did not match expected format", line);
-    };
-    if &caps["sign"] == "L" {
-        -caps["value"].parse::<i32>().unwrap()
-    } else {
-        caps["value"].parse::<i32>().unwrap()
+#[derive(Debug, Clone, PartialEq)]
+enum Instruction {
+    // Relative turn, negative for L and positive for R.
+    Rotate(i32),
+    // Turn to an absolute position along the shorter direction (right on a tie).
+    Set(i32),
+    // Put the dial back on its starting position without turning it, so no zeros are passed.
+    Reset,
+    // Run the body the given number of times.
+    Repeat(u64, Vec<Instruction>),
+}
+
+// Parses dial instructions. The regex is compiled once when the parser is built, so the same
+// parser can be reused for every line of an arbitrarily long instruction stream. Several
+// instructions may share a line, and repeat blocks may span lines, so the parser keeps a stack of
+// the blocks that are still open and only hands back top-level instructions once they are complete.
+struct InstructionParser {
+    re: Regex,
+    open_blocks: Vec<(u64, Vec<Instruction>)>,
+}
+
+impl InstructionParser {
+    fn new() -> Self {
+        InstructionParser {
+            re: Regex::new(
+                r"(?<sign>[LR])(?<value>\d+)|S(?<set>\d+)|(?<reset>Z)|x(?<repeat>\d+)\s*\{|(?<close>\})",
+            )
+            .unwrap(),
+            open_blocks: vec![],
+        }
+    }
+
+    fn parse_line(&mut self, line: &str) -> Vec<Instruction> {
+        let mut out: Vec<Instruction> = vec![];
+        let mut last_end = 0;
+        for caps in self.re.captures_iter(line) {
+            let whole = caps.get(0).unwrap();
+            Self::check_separator(line, &line[last_end..whole.start()]);
+            last_end = whole.end();
+
+            let instruction = if let Some(value) = caps.name("value") {
+                let value = value.as_str().parse::<i32>().unwrap();
+                Instruction::Rotate(if &caps["sign"] == "L" { -value } else { value })
+            } else if let Some(target) = caps.name("set") {
+                let target = target.as_str().parse::<i32>().unwrap();
+                if !(0..100).contains(&target) {
+                    panic!("Line ({}) sets the dial to {}, which is not on the dial", line, target);
+                }
+                Instruction::Set(target)
+            } else if caps.name("reset").is_some() {
+                Instruction::Reset
+            } else if let Some(repeats) = caps.name("repeat") {
+                let repeats = repeats.as_str().parse::<u64>().unwrap();
+                self.open_blocks.push((repeats, vec![]));
+                continue;
+            } else {
+                let Some((repeats, body)) = self.open_blocks.pop() else {
+                    panic!("Line ({}) closes a repeat block that was never opened", line);
+                };
+                Instruction::Repeat(repeats, body)
+            };
+
+            match self.open_blocks.last_mut() {
+                Some((_, body)) => body.push(instruction),
+                None => out.push(instruction),
+            }
+        }
+        Self::check_separator(line, &line[last_end..]);
+        out
+    }
+
+    // Must be called once the stream is exhausted to make sure every repeat block was closed.
+    fn finish(&self) {
+        if !self.open_blocks.is_empty() {
+            panic!("Input ended with {} unclosed repeat block(s)", self.open_blocks.len());
+        }
+    }
+
+    fn check_separator(line: &str, separator: &str) {
+        if !separator.chars().all(|c| c.is_whitespace() || c == ',') {
+            panic!("Line ({}) did not match expected format", line);
+        }
     }
 }
 
-fn parse_file(filename: &str) -> (i32, i32) {
-    let file = File::open(filename).expect("Could not open file");
-    let reader = BufReader::new(file);
+// The effect of a sequence of instructions for every possible starting position of the dial:
+// where the dial ends up and how many zeros were counted on the way. Effects compose, which lets
+// a repeat block be evaluated by repeated squaring instead of unrolling it.
+#[derive(Debug, Clone)]
+struct Effect {
+    position: Vec<i32>,
+    end_zeros: Vec<u64>,
+    all_zeros: Vec<u64>,
+}
+
+impl Effect {
+    fn identity() -> Self {
+        Effect {
+            position: (0..100).collect(),
+            end_zeros: vec![0; 100],
+            all_zeros: vec![0; 100],
+        }
+    }
+
+    fn of_instruction(instruction: &Instruction) -> Self {
+        match instruction {
+            Instruction::Repeat(repeats, body) => Self::of_body(body).pow(*repeats),
+            _ => {
+                let mut effect = Self::identity();
+                for start in 0..100 {
+                    let mut dial = Dial { position: start, end_zeros: 0, all_zeros: 0 };
+                    dial.apply(instruction);
+                    effect.position[start as usize] = dial.position;
+                    effect.end_zeros[start as usize] = dial.end_zeros;
+                    effect.all_zeros[start as usize] = dial.all_zeros;
+                }
+                effect
+            }
+        }
+    }
+
+    fn of_body(body: &[Instruction]) -> Self {
+        body.iter()
+            .fold(Self::identity(), |effect, instruction| effect.then(&Self::of_instruction(instruction)))
+    }
+
+    // Applies self first and then other.
+    fn then(&self, other: &Effect) -> Effect {
+        let mut out = Self::identity();
+        for start in 0..100 {
+            let middle = self.position[start] as usize;
+            out.position[start] = other.position[middle];
+            out.end_zeros[start] = self.end_zeros[start]
+                .checked_add(other.end_zeros[middle])
+                .expect("Zero count overflowed");
+            out.all_zeros[start] = self.all_zeros[start]
+                .checked_add(other.all_zeros[middle])
+                .expect("Zero count overflowed");
+        }
+        out
+    }
+
+    fn pow(&self, mut exponent: u64) -> Effect {
+        let mut out = Self::identity();
+        let mut base = self.clone();
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                out = out.then(&base);
+            }
+            exponent >>= 1;
+            if exponent > 0 {
+                base = base.then(&base);
+            }
+        }
+        out
+    }
+}
+
+#[derive(Debug)]
+struct Dial {
+    position: i32,
+    end_zeros: u64,
+    all_zeros: u64,
+}
+
+impl Dial {
+    fn new() -> Self {
+        Dial {
+            position: 50,
+            end_zeros: 0,
+            all_zeros: 0,
+        }
+    }
+
+    fn rotate(&mut self, amount: i32) {
+        let old_position = self.position;
+        let intermediate_sum = self.position + amount;
+        self.position = intermediate_sum.rem_euclid(100);
+        let wraps: i32 = intermediate_sum / 100 - if intermediate_sum < 0 && old_position != 0 { 1 } else { 0 };
+        self.all_zeros += wraps.unsigned_abs() as u64 + if intermediate_sum == 0 {1} else {0};
+
+        if self.position == 0 {
+            self.end_zeros += 1;
+        }
+    }
 
-    let mut sum = 50;
-    let mut end_zeros: i32 = 0;
-    let mut all_zeros: i32 = 0;
-    for line in reader.lines() {
-        let line = line.expect("Could not read line");
-        let old_sum = sum;
-        let intermediate_sum = sum + parse_line(line.as_str());
-        sum = intermediate_sum.rem_euclid(100);
-        let wraps: i32 = intermediate_sum / 100 - if intermediate_sum < 0 && old_sum != 0 { 1 } else { 0 };
-        all_zeros += wraps.abs() + if intermediate_sum == 0 {1} else {0};
-        
-        if sum == 0 {
-            end_zeros += 1;
+    fn apply(&mut self, instruction: &Instruction) {
+        match instruction {
+            Instruction::Rotate(amount) => self.rotate(*amount),
+            Instruction::Set(target) => {
+                let distance = (target - self.position).rem_euclid(100);
+                self.rotate(if distance > 50 { distance - 100 } else { distance });
+            }
+            Instruction::Reset => self.position = 50,
+            Instruction::Repeat(..) => self.apply_effect(&Effect::of_instruction(instruction)),
         }
     }
-    (end_zeros, all_zeros)
+
+    fn apply_effect(&mut self, effect: &Effect) {
+        let start = self.position as usize;
+        self.position = effect.position[start];
+        self.end_zeros = self.end_zeros.checked_add(effect.end_zeros[start]).expect("Zero count overflowed");
+        self.all_zeros = self.all_zeros.checked_add(effect.all_zeros[start]).expect("Zero count overflowed");
+    }
+}
+
+// Runs the dial over a stream of instruction lines in a single pass. Blank lines are skipped so
+// that piped input with a trailing newline is accepted. Only top-level instructions are executed
+// as they arrive; a repeat block is held until its closing brace has been read.
+fn parse_lines<I, S>(lines: I) -> (u64, u64)
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let mut parser = InstructionParser::new();
+    let mut dial = Dial::new();
+    for line in lines {
+        for instruction in parser.parse_line(line.as_ref()) {
+            dial.apply(&instruction);
+        }
+    }
+    parser.finish();
+    (dial.end_zeros, dial.all_zeros)
+}
+
+fn parse_reader<R: BufRead>(reader: R) -> (u64, u64) {
+    parse_lines(reader.lines().map(|line| line.expect("Could not read line")))
+}
+
+fn parse_file(filename: &str) -> (u64, u64) {
+    let file = File::open(filename).expect("Could not open file");
+    parse_reader(BufReader::new(file))
+}
+
+fn main() {
+    // Passing "-" reads the instructions from stdin instead of the puzzle input.
+    let (end_zeros, all_zeros) = match std::env::args().nth(1).as_deref() {
+        Some("-") => parse_reader(io::stdin().lock()),
+        Some(filename) => parse_file(filename),
+        None => parse_file("inputs/input.txt"),
+    };
+    println!("Zeros at the end of a turn: {}", end_zeros);
+    println!("All zeros encountered: {}",all_zeros);
 }
 
 #[cfg(test)]
@@ -48,10 +259,77 @@ mod tests {
     fn example_2() {
         assert_eq!(parse_file("inputs/example2.txt"), (3, 14));
     }
-}
 
-fn main() {
-    let (end_zeros, all_zeros) = parse_file("inputs/input.txt");
-    println!("Zeros at the end of a turn: {}", end_zeros);
-    println!("All zeros encountered: {}",all_zeros);
+    #[test]
+    fn in_memory_reader() {
+        let input = "L68\nL30\nR48\nL5\nR60\nL55\nL1\nL99\nR14\nL82\n";
+        assert_eq!(parse_reader(input.as_bytes()), (3, 6));
+        assert_eq!(parse_lines(input.lines()), (3, 6));
+    }
+
+    #[test]
+    fn parse_instruction_set() {
+        let mut parser = InstructionParser::new();
+        assert_eq!(
+            parser.parse_line("L5, R10 S0 Z x3{L1 x2{R2}}"),
+            vec![
+                Instruction::Rotate(-5),
+                Instruction::Rotate(10),
+                Instruction::Set(0),
+                Instruction::Reset,
+                Instruction::Repeat(3, vec![
+                    Instruction::Rotate(-1),
+                    Instruction::Repeat(2, vec![Instruction::Rotate(2)]),
+                ]),
+            ]
+        );
+
+        assert_eq!(parser.parse_line("x2 {"), vec![]);
+        assert_eq!(parser.parse_line("R1"), vec![]);
+        assert_eq!(parser.parse_line("}"), vec![Instruction::Repeat(2, vec![Instruction::Rotate(1)])]);
+        parser.finish();
+    }
+
+    #[test]
+    #[should_panic(expected = "unclosed repeat block")]
+    fn unclosed_block() {
+        parse_lines(["x2{", "R1"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "did not match expected format")]
+    fn unknown_instruction() {
+        parse_lines(["R1 Q4"]);
+    }
+
+    #[test]
+    fn set_and_reset() {
+        // From 50, the shortest way to 0 is a right turn of 50 (ties go right), landing on zero.
+        assert_eq!(parse_lines(["S0"]), (1, 1));
+        // From 50 to 10 is a left turn of 40 and from 10 to 80 is a left turn of 30 through zero.
+        assert_eq!(parse_lines(["S10", "S80"]), (0, 1));
+        assert_eq!(parse_lines(["S10", "S80"]), parse_lines(["L40", "L30"]));
+        // Resetting from 99 moves the dial back to 50 without passing zero.
+        assert_eq!(parse_lines(["R49", "Z", "L50"]), (1, 1));
+    }
+
+    #[test]
+    fn repeat_matches_unrolled() {
+        let programs = [
+            ("x7{R37 L5}", "R37 L5 ".repeat(7)),
+            ("x3{L68 x4{R14 S0} Z}", "L68 R14 S0 R14 S0 R14 S0 R14 S0 Z ".repeat(3)),
+            ("L1 x0{R5} R1", "L1 R1".to_string()),
+            ("x250{R199 x3{L50 S75}}", "R199 L50 S75 L50 S75 L50 S75 ".repeat(250)),
+        ];
+        for (compact, unrolled) in programs {
+            assert_eq!(parse_lines([compact]), parse_lines([unrolled]), "{}", compact);
+        }
+    }
+
+    #[test]
+    fn repeat_without_unrolling() {
+        // Every pass turns a full circle plus one, passing zero once, and one pass in a hundred
+        // starts on 99 and also comes to rest on zero.
+        assert_eq!(parse_lines(["x1000000000000{R101}"]), (10_000_000_000, 1_010_000_000_000));
+    }
 }
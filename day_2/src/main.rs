@@ -1,52 +1,71 @@
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 use regex::Regex;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+use std::fmt;
+use std::num::IntErrorKind;
+use std::str::FromStr;
 use std::ops;
+use num_traits::{CheckedAdd, CheckedMul, CheckedSub};
 
+// Sums of invalid IDs. IDs and ranges are u128; the sums can still outgrow them, so every operation
+// on a Total goes through the num-traits Checked* traits (called by path, so u128's inherent
+// methods don't shadow them) and an overflow is reported instead of wrapping. With the `bigint` feature
+// the sums are arbitrary-precision and never overflow. The feature only widens the sums: IDs and
+// range bounds stay u128 either way, and parse_range rejects a bound past 128 bits.
+#[cfg(not(feature = "bigint"))]
+type Total = u128;
+#[cfg(feature = "bigint")]
+type Total = num_bigint::BigUint;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct InvalidId {
-    pattern: i64,
+    pattern: u128,
     // pattern_size: i64,
-    repeats: i64,
-    max_value: i64,
-    id: i64,
+    repeats: u32,
+    max_value: u128,
+    id: u128,
     expired: bool,
+    radix: u32,
 }
 
 impl InvalidId {
-    fn new(pattern: i64, repeats: i64, max_value: i64) -> Self {
-        let pattern_size = pattern.to_string().len() as i64;
-        let mut id: i64 = 0;
-        for k in 0..repeats {
-            id = id + pattern * 10_i64.pow(k as u32 * pattern_size as u32);
-        }
-        let abs_max_value = 10_i64.pow(pattern_size as u32 * repeats as u32) - 1;
+    // The pattern is repeated as a string of digits in the given radix. An ID that does not fit in
+    // a u128 is larger than any max_value, so it is simply marked as expired.
+    fn new(pattern: u128, repeats: u32, max_value: u128, radix: u32) -> Self {
+        check_radix(radix);
+        let pattern_size = number_of_digits(pattern, radix);
+        let id = repunit(radix, pattern_size, repeats).and_then(|multiplier| pattern.checked_mul(multiplier));
+        let abs_max_value = (radix as u128)
+            .checked_pow(pattern_size * repeats)
+            .map_or(u128::MAX, |limit| limit - 1);
 
         InvalidId {
             pattern,
             repeats,
             max_value: if max_value > abs_max_value { abs_max_value } else { max_value },
-            id,
-            expired: id > max_value,
+            id: id.unwrap_or(u128::MAX),
+            expired: id.is_none_or(|id| id > max_value),
+            radix,
         }
     }
 }
 
-impl ops::Add<i64> for InvalidId {
+impl ops::Add<u128> for InvalidId {
     type Output = InvalidId;
 
-    fn add(self, rhs: i64) -> InvalidId {
+    fn add(self, rhs: u128) -> InvalidId {
         let pattern = self.pattern + rhs;
 
-        InvalidId::new(pattern, self.repeats, self.max_value)
+        InvalidId::new(pattern, self.repeats, self.max_value, self.radix)
     }
 }
 
-impl ops::AddAssign<i64> for InvalidId {
-    fn add_assign(&mut self, rhs: i64) {
+impl ops::AddAssign<u128> for InvalidId {
+    fn add_assign(&mut self, rhs: u128) {
         let pattern = self.pattern + rhs;
-        let new_invalid_id = InvalidId::new(pattern, self.repeats, self.max_value);
+        let new_invalid_id = InvalidId::new(pattern, self.repeats, self.max_value, self.radix);
         self.pattern = new_invalid_id.pattern;
         self.id = new_invalid_id.id;
         self.expired = new_invalid_id.expired;
@@ -59,12 +78,16 @@ impl PartialEq for InvalidId {
     }
 }
 
+// The walking solver below visits every candidate ID one at a time. It is kept as a reference
+// implementation to cross-check the closed-form sums and the InvalidIds iterator in the tests.
+
 // This function decomposes a range of numbers into a set of InvalidIds that represent the "shapes"
 // of all invalid ids that can exist in the given number range. E.g. for a 6 digit number, the
 // valid formats can be a 1 digit pattern repeating 6 times, a 2 digit pattern repeating
 // 3 times, or a 3 digit pattern repeating twice. Different patterns are added for each distinct number
 // of digits in the range. The returned vector is sorted according to the value of the invalidid
-fn decompose_into_patterns(start: i64, end: i64) -> Vec<InvalidId> {
+#[cfg(test)]
+fn decompose_into_patterns(start: u128, end: u128) -> Vec<InvalidId> {
     let number_of_digits_min = start.to_string().len();
     let number_of_digit_max = end.to_string().len();
 
@@ -72,85 +95,697 @@ fn decompose_into_patterns(start: i64, end: i64) -> Vec<InvalidId> {
     for digits in number_of_digits_min..=number_of_digit_max {
         for pattern_size in 1..=(digits / 2) {
             if digits % pattern_size == 0 {
-                let abs_min_start = 10_i64.pow((pattern_size - 1) as u32);
-                let pattern_from_start = start.to_string()[..pattern_size].parse::<i64>().unwrap();
+                let abs_min_start = 10_u128.pow((pattern_size - 1) as u32);
+                let pattern_from_start = start.to_string()[..pattern_size].parse::<u128>().unwrap();
                 out.push(InvalidId::new(
                     if abs_min_start > pattern_from_start || digits != number_of_digits_min {abs_min_start} else {pattern_from_start},
-                    (digits / pattern_size) as i64,
+                    (digits / pattern_size) as u32,
                     end,
+                    10,
                 ));
             }
         }
     }
-    out.sort_by(|a, b| a.id.cmp(&b.id));
+    out.sort_by_key(|a| a.id);
     out
 }
 
-fn parse_range(range: &str) -> (i64, i64) {
-    let re = Regex::new(r"(?<start>\d+)-(?<end>\d+)").unwrap();
+// Iterates over the invalid IDs in a range in ascending order. Every pattern shape (number of digits
+// and pattern size) is an InvalidId that steps through its own ascending sequence, and the shapes
+// are merged with a min-heap. An ID produced by several shapes (111111 is 1 x 6, 11 x 3 and 111 x 2)
+// is yielded once, as the shape with the shortest pattern.
+struct InvalidIds {
+    shapes: Vec<InvalidId>,
+    // (id, shortest pattern first, index into shapes)
+    heap: BinaryHeap<Reverse<(u128, Reverse<u32>, usize)>>,
+}
+
+impl InvalidIds {
+    fn in_range(start: u128, end: u128, radix: u32) -> Self {
+        check_radix(radix);
+        let mut shapes: Vec<InvalidId> = vec![];
+        for digits in number_of_digits(start, radix)..=number_of_digits(end, radix) {
+            let lo = start.max((radix as u128).pow(digits - 1));
+            for pattern_size in (1..digits).filter(|&p| digits.is_multiple_of(p)) {
+                let multiplier = repunit(radix, pattern_size, digits / pattern_size).unwrap();
+                let first_pattern = lo.div_ceil(multiplier);
+                shapes.push(InvalidId::new(
+                    first_pattern.max((radix as u128).pow(pattern_size - 1)),
+                    digits / pattern_size,
+                    end,
+                    radix,
+                ));
+            }
+        }
+
+        let heap = shapes
+            .iter()
+            .enumerate()
+            .filter(|(_, shape)| !shape.expired)
+            .map(|(i, shape)| Reverse((shape.id, Reverse(shape.repeats), i)))
+            .collect();
+        InvalidIds { shapes, heap }
+    }
+
+    fn advance(&mut self, index: usize) {
+        let shape = &mut self.shapes[index];
+        *shape += 1;
+        if !shape.expired {
+            self.heap.push(Reverse((shape.id, Reverse(shape.repeats), index)));
+        }
+    }
+}
+
+impl Iterator for InvalidIds {
+    type Item = InvalidId;
+
+    fn next(&mut self) -> Option<InvalidId> {
+        let Reverse((id, _, index)) = self.heap.pop()?;
+        let out = self.shapes[index].clone();
+        self.advance(index);
+        while let Some(&Reverse((duplicate_id, _, duplicate_index))) = self.heap.peek() {
+            if duplicate_id != id {
+                break;
+            }
+            self.heap.pop();
+            self.advance(duplicate_index);
+        }
+        Some(out)
+    }
+}
+
+// Digits go from 0-9 and then a-z, so radixes past 36 have no digits to write them with. A radix
+// below 2 has no numbers with more than one digit, and counting their digits would never end.
+fn check_radix(radix: u32) {
+    if !(2..=36).contains(&radix) {
+        panic!("Radix must be between 2 and 36, not {}", radix);
+    }
+}
+
+fn parse_range(range: &str, radix: u32) -> (u128, u128) {
+    check_radix(radix);
+    let re = Regex::new(r"(?<start>[[:alnum:]]+)-(?<end>[[:alnum:]]+)").unwrap();
     let Some(caps) = re.captures(range) else {
         panic!("Range ({}) did not match expected format", range);
     };
-    let start = caps["start"].parse::<i64>().unwrap();
-    let end = caps["end"].parse::<i64>().unwrap();
-    (start, end)
+    let parse = |value: &str| {
+        u128::from_str_radix(value, radix).unwrap_or_else(|e| match e.kind() {
+            IntErrorKind::PosOverflow => panic!("Range ({}) does not fit in 128 bits", range),
+            _ => panic!("Range ({}) is not made of base {} numbers", range, radix),
+        })
+    };
+    (parse(&caps["start"]), parse(&caps["end"]))
 }
 
-fn parse_file(filename: &str) -> (i64, i64) {
-    let file = File::open(filename).expect("Could not open file");
-    let reader = BufReader::new(file);
+#[cfg(test)]
+fn sum_by_walking(start: u128, end: u128) -> (u128, u128) {
+    let mut sum_total: u128 = 0;
+    let mut sum_part_1: u128 = 0;
+    let mut invalid_id_patterns = decompose_into_patterns(start, end);
+    while !invalid_id_patterns.is_empty() {
+        if invalid_id_patterns[0].expired || invalid_id_patterns[0].id > end {
+            invalid_id_patterns.remove(0);
+            continue;
+        }
 
-    let mut sum_total: i64 = 0;
-    let mut sum_part_1: i64 = 0;
-    for line in reader.lines() {
-        let line: String = line.expect("Could not read line");
-        for parts in line.split(',') {
-            let part = parts.trim();
-            let (start, end) = parse_range(part);
-            let mut invalid_id_patterns = decompose_into_patterns(start, end);
-            while invalid_id_patterns.len() > 0 {
-                if invalid_id_patterns[0].expired || invalid_id_patterns[0].id > end {
-                    invalid_id_patterns.remove(0);
-                    continue;
+        let mut changed = false;
+        for i in 1..invalid_id_patterns.len() {
+            if invalid_id_patterns[0] == invalid_id_patterns[i] {
+                // Shapes can meet on an ID below the start of the range, which is not part of it.
+                if invalid_id_patterns[i].repeats == 2 && invalid_id_patterns[i].id >= start {
+                    sum_part_1 += invalid_id_patterns[i].id;
                 }
+                invalid_id_patterns[i] += 1;
+                changed = true;
+            } else if invalid_id_patterns[0].id < invalid_id_patterns[i].id {
+                break;
+            }
+        }
+        if changed {
+            invalid_id_patterns.sort_by_key(|a| a.id);
+            continue;
+        }
 
-                let mut changed = false;
-                for i in 1..invalid_id_patterns.len() {
-                    if invalid_id_patterns[0] == invalid_id_patterns[i] {
-                        if invalid_id_patterns[i].repeats == 2 {
-                            sum_part_1 += invalid_id_patterns[i].id;
-                        }
-                        invalid_id_patterns[i] += 1;
-                        changed = true;
-                    } else if invalid_id_patterns[0].id < invalid_id_patterns[i].id {
-                        break;
-                    }
-                }
-                if changed {
-                    invalid_id_patterns.sort_by(|a, b| a.id.cmp(&b.id));
+        if invalid_id_patterns[0].id >= start && invalid_id_patterns[0].id <= end {
+            sum_part_1 += if invalid_id_patterns[0].repeats == 2 { invalid_id_patterns[0].id } else { 0 };
+            sum_total += invalid_id_patterns[0].id;
+        }
+
+        invalid_id_patterns[0] += 1;
+        invalid_id_patterns.sort_by_key(|a| a.id);
+    }
+    (sum_part_1, sum_total)
+}
+
+fn number_of_digits(value: u128, radix: u32) -> u32 {
+    let mut digits = 1;
+    let mut value = value / radix as u128;
+    while value > 0 {
+        digits += 1;
+        value /= radix as u128;
+    }
+    digits
+}
+
+// The multiplier that repeats a `pattern_size` digit pattern `repeats` times, 10..010..01 read in
+// the given radix. None if it does not fit in a u128.
+fn repunit(radix: u32, pattern_size: u32, repeats: u32) -> Option<u128> {
+    let shift = (radix as u128).checked_pow(pattern_size)?;
+    let mut multiplier: u128 = 0;
+    for _ in 0..repeats {
+        multiplier = multiplier.checked_mul(shift)?.checked_add(1)?;
+    }
+    Some(multiplier)
+}
+
+fn mobius(n: u32) -> i32 {
+    let mut n = n;
+    let mut out = 1;
+    let mut factor = 2;
+    while factor * factor <= n {
+        if n.is_multiple_of(factor) {
+            n /= factor;
+            if n.is_multiple_of(factor) {
+                return 0;
+            }
+            out = -out;
+        }
+        factor += 1;
+    }
+    if n > 1 { -out } else { out }
+}
+
+// The number of invalid IDs in some set and their sum.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Tally {
+    count: u128,
+    sum: Total,
+}
+
+impl Tally {
+    fn checked_add(&self, other: &Tally) -> Option<Tally> {
+        Some(Tally {
+            count: self.count.checked_add(other.count)?,
+            sum: CheckedAdd::checked_add(&self.sum, &other.sum)?,
+        })
+    }
+
+    // Only used where other is known to be a subset of self.
+    fn subtract(&self, other: &Tally) -> Tally {
+        Tally {
+            count: self.count - other.count,
+            sum: CheckedSub::checked_sub(&self.sum, &other.sum).expect("Tally cannot be negative"),
+        }
+    }
+}
+
+// Count and sum of every number in [lo, hi] that has `digits` digits and is made of a `pattern_size`
+// digit pattern repeated. Such numbers are the pattern times the multiplier 10..010..01 (read in the
+// given radix), so the sum is the multiplier times an arithmetic series over the patterns that land
+// inside the range. [lo, hi] must only contain numbers with `digits` digits. None on overflow.
+fn tally_periodic(lo: u128, hi: u128, digits: u32, pattern_size: u32, radix: u32) -> Option<Tally> {
+    // Every number in the range fits in a u128, so its multiplier does too.
+    let multiplier = repunit(radix, pattern_size, digits / pattern_size).unwrap();
+    let first = lo.div_ceil(multiplier);
+    let last = hi / multiplier;
+    if first > last {
+        return Some(Tally::default());
+    }
+    // The multiplier is at least radix + 1, so first + last cannot overflow. One of the two
+    // factors of the series is even, and halving it first keeps the division exact.
+    let count = last - first + 1;
+    let (mut pair_sum, mut pairs) = (first + last, count);
+    if pair_sum.is_multiple_of(2) {
+        pair_sum /= 2;
+    } else {
+        pairs /= 2;
+    }
+    let product = CheckedMul::checked_mul(&Total::from(multiplier), &Total::from(pair_sum))?;
+    Some(Tally {
+        count,
+        sum: CheckedMul::checked_mul(&product, &Total::from(pairs))?,
+    })
+}
+
+// For each pattern size `p` that divides `digits` (excluding `digits` itself), the count and sum of
+// the numbers in [lo, hi] whose shortest repeating pattern is exactly `p` digits long. A number made
+// of a `q` digit pattern is also made of every multiple of `q` that divides `digits` (111111 is
+// 1 x 6, 11 x 3 and 111 x 2), so the periodic tallies overcount. Möbius inversion over the divisors
+// removes the double counting: exact(p) = sum over q | p of mu(p / q) * periodic(q). The positive
+// and negative terms are added up separately so the subtraction happens once, on a non-negative
+// result.
+fn exact_pattern_tallies(lo: u128, hi: u128, digits: u32, radix: u32) -> Option<Vec<(u32, Tally)>> {
+    let pattern_sizes: Vec<u32> = (1..digits).filter(|&p| digits.is_multiple_of(p)).collect();
+    let periodic: Vec<Tally> = pattern_sizes
+        .iter()
+        .map(|&p| tally_periodic(lo, hi, digits, p, radix))
+        .collect::<Option<_>>()?;
+    pattern_sizes
+        .iter()
+        .map(|&p| {
+            let mut added = Tally::default();
+            let mut removed = Tally::default();
+            for (&q, tally) in pattern_sizes.iter().zip(&periodic) {
+                if !p.is_multiple_of(q) {
                     continue;
                 }
+                match mobius(p / q) {
+                    1 => added = added.checked_add(tally)?,
+                    -1 => removed = removed.checked_add(tally)?,
+                    _ => {}
+                }
+            }
+            Some((p, added.subtract(&removed)))
+        })
+        .collect()
+}
 
-                if invalid_id_patterns[0].id >= start && invalid_id_patterns[0].id <= end {
-                    sum_part_1 += if invalid_id_patterns[0].repeats == 2 { invalid_id_patterns[0].id } else { 0 };
-                    sum_total += invalid_id_patterns[0].id;
+// Which repeat counts make an ID invalid.
+#[derive(Debug, Clone, PartialEq)]
+enum RepeatCounts {
+    Exactly(u32),
+    AtLeast(u32),
+    OneOf(Vec<u32>),
+    Prime,
+}
+
+impl RepeatCounts {
+    fn allows(&self, repeats: u32) -> bool {
+        match self {
+            RepeatCounts::Exactly(k) => repeats == *k,
+            RepeatCounts::AtLeast(k) => repeats >= *k,
+            RepeatCounts::OneOf(allowed) => allowed.contains(&repeats),
+            RepeatCounts::Prime => {
+                repeats >= 2 && (2..repeats).take_while(|f| f * f <= repeats).all(|f| !repeats.is_multiple_of(f))
+            }
+        }
+    }
+}
+
+// How an ID's repeat count is read. The ID's shortest pattern repeats some number of times, but the
+// ID can also be read as a longer pattern repeated any divisor of that count (111111 is 1 x 6, 11 x 3
+// and 111 x 2).
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Reading {
+    // Every reading counts, as in the puzzle: 111111 is 111 repeated twice for part 1. Since every
+    // count of 2 or more has a prime divisor, Prime is then the same as AtLeast(2).
+    AnyDivisor,
+    // Only the shortest pattern's count does, so 111111 is 6 repeats and nothing else.
+    Shortest,
+}
+
+// The repeat counts that make an ID invalid and how an ID's count is read.
+#[derive(Debug, Clone, PartialEq)]
+struct RepeatPolicy {
+    counts: RepeatCounts,
+    reading: Reading,
+}
+
+impl RepeatPolicy {
+    fn new(counts: RepeatCounts, reading: Reading) -> Self {
+        RepeatPolicy { counts, reading }
+    }
+
+    fn part_1() -> Self {
+        RepeatPolicy::new(RepeatCounts::Exactly(2), Reading::AnyDivisor)
+    }
+
+    fn part_2() -> Self {
+        RepeatPolicy::new(RepeatCounts::AtLeast(2), Reading::AnyDivisor)
+    }
+
+    // Whether an ID whose shortest pattern repeats `max_repeats` times is invalid under the policy. A
+    // single repeat is never invalid.
+    fn matches(&self, max_repeats: u32) -> bool {
+        match self.reading {
+            Reading::AnyDivisor => (2..=max_repeats).any(|r| max_repeats.is_multiple_of(r) && self.counts.allows(r)),
+            Reading::Shortest => max_repeats >= 2 && self.counts.allows(max_repeats),
+        }
+    }
+}
+
+// Parses "exactly:<k>", "at-least:<k>", "one-of:<k>,<k>,..." or "prime", read by any divisor, or
+// any of them after "shortest:" to go by the shortest pattern only.
+impl FromStr for RepeatPolicy {
+    type Err = String;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        let (counts, reading) = match policy.strip_prefix("shortest:") {
+            Some(counts) => (counts, Reading::Shortest),
+            None => (policy, Reading::AnyDivisor),
+        };
+        let (name, value) = counts.split_once(':').unwrap_or((counts, ""));
+        let count = |value: &str| value.trim().parse::<u32>().map_err(|_| format!("Invalid repeat count ({})", value));
+        let counts = match name {
+            "exactly" => RepeatCounts::Exactly(count(value)?),
+            "at-least" => RepeatCounts::AtLeast(count(value)?),
+            "one-of" => RepeatCounts::OneOf(value.split(',').map(count).collect::<Result<_, _>>()?),
+            "prime" if value.is_empty() => RepeatCounts::Prime,
+            _ => return Err(format!("Unknown repeat policy ({})", policy)),
+        };
+        Ok(RepeatPolicy::new(counts, reading))
+    }
+}
+
+// Where the invalid IDs of one range come from. `by_repeats` files every ID under the number of times
+// its shortest pattern repeats, so 111111 is counted under 6 even though it also counts for part 1
+// as 111 twice. Any RepeatPolicy decides by that number alone, so the tally for a policy is the sum
+// of the matching entries. `ids` is only filled in when the report is asked to list them.
+#[derive(Debug, Clone, PartialEq)]
+struct RangeReport {
+    start: u128,
+    end: u128,
+    total: Tally,
+    by_repeats: BTreeMap<u32, Tally>,
+    ids: Option<Vec<u128>>,
+}
+
+impl RangeReport {
+    // Tallies the invalid IDs in [start, end] without visiting them. None if a sum overflows.
+    fn new(start: u128, end: u128, radix: u32, list_ids: bool) -> Option<Self> {
+        check_radix(radix);
+        let mut report = RangeReport {
+            start,
+            end,
+            total: Tally::default(),
+            by_repeats: BTreeMap::new(),
+            ids: None,
+        };
+        for digits in number_of_digits(start, radix)..=number_of_digits(end, radix) {
+            let lo = start.max((radix as u128).pow(digits - 1));
+            let hi = end.min((radix as u128).checked_pow(digits).map_or(u128::MAX, |limit| limit - 1));
+            if lo > hi {
+                continue;
+            }
+            for (pattern_size, tally) in exact_pattern_tallies(lo, hi, digits, radix)? {
+                if tally.count == 0 {
+                    continue;
                 }
-                
-                invalid_id_patterns[0] += 1;
-                invalid_id_patterns.sort_by(|a, b| a.id.cmp(&b.id));
+                report.total = report.total.checked_add(&tally)?;
+                let by_repeats = report.by_repeats.entry(digits / pattern_size).or_default();
+                *by_repeats = by_repeats.checked_add(&tally)?;
             }
         }
+        if list_ids {
+            report.ids = Some(InvalidIds::in_range(start, end, radix).map(|invalid_id| invalid_id.id).collect());
+        }
+        Some(report)
     }
-    (sum_part_1, sum_total)
+
+    // The IDs that match the policy. They are a subset of the total, which was already checked
+    // for overflow.
+    fn tally(&self, policy: &RepeatPolicy) -> Tally {
+        self.by_repeats
+            .iter()
+            .filter(|(repeats, _)| policy.matches(**repeats))
+            .fold(Tally::default(), |out, (_, tally)| out.checked_add(tally).unwrap())
+    }
+
+    fn part_1(&self) -> Tally {
+        self.tally(&RepeatPolicy::part_1())
+    }
+}
+
+impl fmt::Display for RangeReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let part_1 = self.part_1();
+        writeln!(
+            f,
+            "{}-{}: {} invalid IDs, sum {} (part 1: {} IDs, sum {})",
+            self.start, self.end, self.total.count, self.total.sum, part_1.count, part_1.sum
+        )?;
+        for (repeats, tally) in &self.by_repeats {
+            writeln!(f, "  repeated {} times: {} IDs, sum {}", repeats, tally.count, tally.sum)?;
+        }
+        if let Some(ids) = &self.ids {
+            let ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+            writeln!(f, "  IDs: {}", ids.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+// A range as it appeared in the input. `line` and `index` are 1-based: the line of the file and the
+// position of the range among the comma separated ranges on that line.
+#[derive(Debug, Clone, PartialEq)]
+struct InputRange {
+    start: u128,
+    end: u128,
+    text: String,
+    line: usize,
+    index: usize,
+}
+
+impl fmt::Display for InputRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (line {}, range {})", self.text, self.line, self.index)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum RangeIssue {
+    Reversed(InputRange),
+    LeadingZeros(InputRange),
+    Duplicate { range: InputRange, first: InputRange },
+    Overlap { range: InputRange, other: InputRange },
+}
+
+impl fmt::Display for RangeIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RangeIssue::Reversed(range) => write!(f, "{} ends before it starts", range),
+            RangeIssue::LeadingZeros(range) => write!(f, "{} has a number with leading zeros", range),
+            RangeIssue::Duplicate { range, first } => write!(f, "{} duplicates {}", range, first),
+            RangeIssue::Overlap { range, other } => write!(f, "{} overlaps {}", range, other),
+        }
+    }
+}
+
+fn read_ranges(filename: &str, radix: u32) -> Vec<InputRange> {
+    let file = File::open(filename).expect("Could not open file");
+    let reader = BufReader::new(file);
+
+    let mut out: Vec<InputRange> = vec![];
+    for (line_index, line) in reader.lines().enumerate() {
+        let line: String = line.expect("Could not read line");
+        for (index, parts) in line.split(',').enumerate() {
+            let part = parts.trim();
+            if part.is_empty() {
+                continue;
+            }
+            let (start, end) = parse_range(part, radix);
+            out.push(InputRange { start, end, text: part.to_string(), line: line_index + 1, index: index + 1 });
+        }
+    }
+    out
+}
+
+// Reports reversed ranges, numbers written with leading zeros, ranges that repeat an earlier range
+// exactly, and ranges that share IDs with another one. Each overlapping range is reported against
+// the range before it (in order of start) that reaches furthest, so a range covered by several
+// others is only reported once. Issues come out in input order.
+fn validate_ranges(ranges: &[InputRange]) -> Vec<RangeIssue> {
+    let mut out: Vec<(usize, RangeIssue)> = vec![];
+    for (i, range) in ranges.iter().enumerate() {
+        if range.start > range.end {
+            out.push((i, RangeIssue::Reversed(range.clone())));
+        }
+        if range.text.split('-').any(|number| number.len() > 1 && number.starts_with('0')) {
+            out.push((i, RangeIssue::LeadingZeros(range.clone())));
+        }
+    }
+
+    let mut order: Vec<usize> = (0..ranges.len()).filter(|&i| ranges[i].start <= ranges[i].end).collect();
+    order.sort_by_key(|&i| (ranges[i].start, ranges[i].end, i));
+    let mut furthest: Option<usize> = None;
+    for (position, &i) in order.iter().enumerate() {
+        let range = &ranges[i];
+        if position > 0 {
+            let previous = &ranges[order[position - 1]];
+            if (previous.start, previous.end) == (range.start, range.end) {
+                let first = order[..position]
+                    .iter()
+                    .map(|&j| &ranges[j])
+                    .find(|earlier| (earlier.start, earlier.end) == (range.start, range.end))
+                    .unwrap();
+                out.push((i, RangeIssue::Duplicate { range: range.clone(), first: first.clone() }));
+                continue;
+            }
+        }
+        if let Some(f) = furthest
+            && ranges[f].end >= range.start
+        {
+            let (earlier, later) = if f < i { (f, i) } else { (i, f) };
+            out.push((later, RangeIssue::Overlap { range: ranges[later].clone(), other: ranges[earlier].clone() }));
+        }
+        if furthest.is_none_or(|f| range.end > ranges[f].end) {
+            furthest = Some(i);
+        }
+    }
+    out.sort_by_key(|(i, _)| *i);
+    out.into_iter().map(|(_, issue)| issue).collect()
+}
+
+// Sorts the ranges and coalesces the ones that overlap or touch, so every ID is covered at most
+// once. Reversed ranges cover nothing and are dropped.
+fn merge_ranges(ranges: &[InputRange]) -> Vec<(u128, u128)> {
+    let mut sorted: Vec<(u128, u128)> = ranges
+        .iter()
+        .filter(|range| range.start <= range.end)
+        .map(|range| (range.start, range.end))
+        .collect();
+    sorted.sort();
+    let mut out: Vec<(u128, u128)> = vec![];
+    for (start, end) in sorted {
+        match out.last_mut() {
+            Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+            _ => out.push((start, end)),
+        }
+    }
+    out
+}
+
+// Reads the ranges in the given radix and sums the IDs that repeat a pattern of digits in that
+// radix, once for each policy. With `merge`, overlapping ranges are merged first so an ID in
+// several ranges is only counted once. Returns an error naming the range at which a sum overflowed.
+fn sum_file(filename: &str, radix: u32, policies: &[RepeatPolicy], merge: bool) -> Result<Vec<Total>, String> {
+    let ranges = read_ranges(filename, radix);
+    let ranges: Vec<(u128, u128)> = if merge {
+        merge_ranges(&ranges)
+    } else {
+        ranges.iter().map(|range| (range.start, range.end)).collect()
+    };
+    let mut sums = vec![Total::from(0_u128); policies.len()];
+    for (start, end) in ranges {
+        let overflow = || format!("Sum of invalid IDs overflowed at range ({}-{})", start, end);
+        let report = RangeReport::new(start, end, radix, false).ok_or_else(overflow)?;
+        for (sum, policy) in sums.iter_mut().zip(policies) {
+            *sum = CheckedAdd::checked_add(sum, &report.tally(policy).sum).ok_or_else(overflow)?;
+        }
+    }
+    Ok(sums)
+}
+
+fn parse_file(filename: &str, radix: u32, merge: bool) -> Result<(Total, Total), String> {
+    let sums = sum_file(filename, radix, &[RepeatPolicy::part_1(), RepeatPolicy::part_2()], merge)?;
+    let [part_1, total] = <[Total; 2]>::try_from(sums).unwrap();
+    Ok((part_1, total))
+}
+
+// One report per range in the file, in input order.
+fn report_file(filename: &str, radix: u32, list_ids: bool) -> Result<Vec<RangeReport>, String> {
+    read_ranges(filename, radix)
+        .into_iter()
+        .map(|InputRange { start, end, .. }| {
+            RangeReport::new(start, end, radix, list_ids)
+                .ok_or_else(|| format!("Sum of invalid IDs overflowed at range ({}-{})", start, end))
+        })
+        .collect()
+}
+
+fn main() {
+    // "--radix <n>" reads the ranges in base n and looks for patterns of base n digits.
+    // "--report" prints a breakdown of every range in the input, and "--ids" prints it with the IDs
+    // added.
+    // "--policy <policy>" sums the IDs that are invalid under another repeat policy, such as
+    // "exactly:3" or "shortest:prime".
+    // "--validate" lists problems with the ranges, and "--merge" merges overlapping ranges before
+    // summing.
+    let mut args = std::env::args().skip(1);
+    let mut radix = 10;
+    let mut report = false;
+    let mut list_ids = false;
+    let mut policy: Option<RepeatPolicy> = None;
+    let mut validate = false;
+    let mut merge = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--radix" => {
+                radix = args.next().and_then(|r| r.parse().ok()).expect("Expected a radix after --radix");
+                check_radix(radix);
+            }
+            "--report" => report = true,
+            "--ids" => {
+                report = true;
+                list_ids = true;
+            }
+            "--validate" => validate = true,
+            "--merge" => merge = true,
+            "--policy" => {
+                let value = args.next().expect("Expected a policy after --policy");
+                policy = Some(value.parse().unwrap_or_else(|error| panic!("{}", error)));
+            }
+            _ => panic!("Unknown argument ({})", arg),
+        }
+    }
+
+    if validate {
+        let issues = validate_ranges(&read_ranges("inputs/input.txt", radix));
+        issues.iter().for_each(|issue| println!("{}", issue));
+        if !issues.is_empty() {
+            std::process::exit(1);
+        }
+        println!("No problems found");
+        return;
+    }
+
+    if report {
+        match report_file("inputs/input.txt", radix, list_ids) {
+            Ok(reports) => reports.iter().for_each(|range_report| print!("{}", range_report)),
+            Err(error) => {
+                eprintln!("{}", error);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(policy) = policy {
+        match sum_file("inputs/input.txt", radix, std::slice::from_ref(&policy), merge) {
+            Ok(sums) => println!("Sum of invalid IDs with policy {:?}: {}", policy, sums[0]),
+            Err(error) => {
+                eprintln!("{}", error);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    match parse_file("inputs/input.txt", radix, merge) {
+        Ok((part_1, total)) => println!("Sum of invalid IDs. Part 1: {}, Part 2: {}", part_1, total),
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    }
+
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    // Totals as plain integers, so the tests read the same with and without `bigint`. The
+    // conversion is only a no-op without it.
+    #[allow(clippy::useless_conversion, clippy::clone_on_copy)]
+    fn to_u128(total: &Total) -> u128 {
+        u128::try_from(total.clone()).unwrap()
+    }
+
+    fn sum_invalid_ids(start: u128, end: u128, radix: u32) -> Option<(Total, Total)> {
+        let report = RangeReport::new(start, end, radix, false)?;
+        Some((report.part_1().sum, report.tally(&RepeatPolicy::part_2()).sum))
+    }
+
+    fn sums(start: u128, end: u128, radix: u32) -> (u128, u128) {
+        let (part_1, total) = sum_invalid_ids(start, end, radix).expect("Sum overflowed");
+        (to_u128(&part_1), to_u128(&total))
+    }
+
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn invalid_id_struct() {
-        let mut invalid_id = InvalidId::new(12, 3, 1500);
+        let mut invalid_id = InvalidId::new(12, 3, 1500, 10);
         assert_eq!(invalid_id.id, 121212);
         assert_eq!(invalid_id.expired, true);
         invalid_id += 1;
@@ -158,11 +793,11 @@ mod tests {
         invalid_id += 11;
         assert_eq!(invalid_id.id, 242424);
 
-        let invalid_id = InvalidId::new(34, 2, 4000);
+        let invalid_id = InvalidId::new(34, 2, 4000, 10);
         assert_eq!(invalid_id.id, 3434);
         assert_eq!(invalid_id.expired, false);
 
-        let mut invalid_id = InvalidId::new(99, 4, 10000000000);
+        let mut invalid_id = InvalidId::new(99, 4, 10000000000, 10);
         assert_eq!(invalid_id.id, 99999999);
         assert_eq!(invalid_id.expired, false);
         assert_eq!(invalid_id.max_value, 99999999);
@@ -170,7 +805,7 @@ mod tests {
         assert_eq!(invalid_id.id, 100100100100);
         assert_eq!(invalid_id.expired, true);
 
-        let invalid_id = InvalidId::new(7, 5, 10000000);
+        let invalid_id = InvalidId::new(7, 5, 10000000, 10);
         assert_eq!(invalid_id.id, 77777);
         assert_eq!(invalid_id.expired, false);
         assert_eq!(invalid_id.max_value, 99999);
@@ -200,12 +835,455 @@ mod tests {
 
     #[test]
     fn example_1_sum() {
-        assert_eq!(parse_file("inputs/example.txt"), (1227775554, 4174379265));
+        let (part_1, total) = parse_file("inputs/example.txt", 10, false).unwrap();
+        assert_eq!((part_1, total), (Total::from(1227775554_u128), Total::from(4174379265_u128)));
     }
-}
 
-fn main() {
-    let (part_1, total) = parse_file("inputs/input.txt");
-    println!("Sum of invalid IDs. Part 1: {}, Part 2: {}", part_1, total);
+    #[test]
+    fn walking_skips_ids_below_start() {
+        // 333333 is below the range but is where the 3 x 6, 33 x 3 and 333 x 2 shapes all start. The
+        // walker used to add it to part 1 while stepping the coinciding shapes past it.
+        assert_eq!(sum_by_walking(333334, 333400), (0, 0));
+        // 334334 to 343343 repeat twice, and 343434 three times.
+        assert_eq!(sum_by_walking(333334, 343434), (3388385, 3388385 + 343434));
+    }
+
+    #[test]
+    fn mobius_values() {
+        let values: Vec<i32> = (1..=12).map(mobius).collect();
+        assert_eq!(values, vec![1, -1, -1, 0, -1, 1, -1, 0, 0, 1, -1, 0]);
+    }
 
+    #[test]
+    fn exact_patterns_remove_double_counting() {
+        // 111111 and 222222 are made of a single repeated digit, so they must only appear under
+        // pattern size 1 even though they also repeat with pattern sizes 2 and 3.
+        let sums: Vec<(u32, u128)> = exact_pattern_tallies(111111, 222222, 6, 10)
+            .unwrap()
+            .into_iter()
+            .map(|(pattern_size, tally)| (pattern_size, to_u128(&tally.sum)))
+            .collect();
+        assert_eq!(sums[0], (1, 111111 + 222222));
+        assert_eq!(sums[1], (2, (12..=21).filter(|p| p % 11 != 0).map(|p| p * 10101).sum()));
+        assert_eq!(sums[2], (3, (112..=222).filter(|p| p % 111 != 0).map(|p| p * 1001).sum()));
+    }
+
+    #[test]
+    fn closed_form_matches_walking() {
+        let ranges = [
+            (1, 9), (11, 22), (95, 115), (998, 1012), (100, 1500), (5000, 150000),
+            (222220, 222224), (1000, 999999), (123456, 987654), (1188511880, 1188511890),
+            (333790, 431800),
+        ];
+        for (start, end) in ranges {
+            assert_eq!(sums(start, end, 10), sum_by_walking(start, end), "{}-{}", start, end);
+        }
+    }
+
+    #[test]
+    fn iterate_invalid_ids() {
+        let found: Vec<(u128, u128, u32)> = InvalidIds::in_range(95, 1012, 10)
+            .map(|invalid_id| (invalid_id.id, invalid_id.pattern, invalid_id.repeats))
+            .collect();
+        assert_eq!(found, vec![(99, 9, 2), (111, 1, 3), (222, 2, 3), (333, 3, 3), (444, 4, 3),
+            (555, 5, 3), (666, 6, 3), (777, 7, 3), (888, 8, 3), (999, 9, 3), (1010, 10, 2)]);
+
+        let found: Vec<(u128, u128, u32)> = InvalidIds::in_range(111110, 121213, 10)
+            .map(|invalid_id| (invalid_id.id, invalid_id.pattern, invalid_id.repeats))
+            .collect();
+        assert_eq!(found, vec![(111111, 1, 6), (112112, 112, 2), (113113, 113, 2), (114114, 114, 2),
+            (115115, 115, 2), (116116, 116, 2), (117117, 117, 2), (118118, 118, 2), (119119, 119, 2),
+            (120120, 120, 2), (121121, 121, 2), (121212, 12, 3)]);
+
+        assert_eq!(InvalidIds::in_range(1, 10, 10).count(), 0);
+        assert_eq!(InvalidIds::in_range(4, 14, 10).map(|invalid_id| invalid_id.id).collect::<Vec<_>>(), vec![11]);
+    }
+
+    #[test]
+    fn iterator_matches_closed_form() {
+        let ranges = [(1, 9), (4, 14), (95, 115), (1, 1000000), (5000, 150000), (333790, 431800),
+            (1188511880, 1188511890), (9898963615, 9899009366)];
+        for (start, end) in ranges {
+            let mut sum_part_1 = 0;
+            let mut sum_total = 0;
+            let mut previous = 0;
+            for invalid_id in InvalidIds::in_range(start, end, 10) {
+                assert!(invalid_id.id > previous && invalid_id.id >= start && invalid_id.id <= end);
+                previous = invalid_id.id;
+                sum_total += invalid_id.id;
+                // Repeated exactly twice when the shortest pattern fits an even number of times.
+                if invalid_id.repeats % 2 == 0 {
+                    sum_part_1 += invalid_id.id;
+                }
+            }
+            assert_eq!((sum_part_1, sum_total), sums(start, end, 10), "{}-{}", start, end);
+        }
+    }
+
+    fn to_radix_string(value: u128, radix: u32) -> String {
+        if value == 0 {
+            return "0".to_string();
+        }
+        let mut digits: Vec<char> = vec![];
+        let mut value = value;
+        while value > 0 {
+            digits.push(char::from_digit((value % radix as u128) as u32, radix).unwrap());
+            value /= radix as u128;
+        }
+        digits.iter().rev().collect()
+    }
+
+    // Checks every number in [start, end] by writing it out in the radix and testing each pattern
+    // size against the digit string.
+    fn brute_force(start: u128, end: u128, radix: u32) -> (u128, u128) {
+        let mut sum_part_1 = 0;
+        let mut sum_total = 0;
+        for value in start..=end {
+            let digits: Vec<char> = to_radix_string(value, radix).chars().collect();
+            let repeats_with = |pattern_size: usize| {
+                digits.len().is_multiple_of(pattern_size) && digits.chunks(pattern_size).all(|c| c == &digits[..pattern_size])
+            };
+            if digits.len().is_multiple_of(2) && repeats_with(digits.len() / 2) {
+                sum_part_1 += value;
+            }
+            if (1..digits.len()).any(repeats_with) {
+                sum_total += value;
+            }
+        }
+        (sum_part_1, sum_total)
+    }
+
+    #[test]
+    fn radix_parsing() {
+        assert_eq!(parse_range("ff-1aB", 16), (255, 427));
+        assert_eq!(parse_range("101-111", 2), (5, 7));
+        assert_eq!(to_radix_string(427, 16), "1ab");
+        assert_eq!(to_radix_string(0, 2), "0");
+        assert_eq!(number_of_digits(255, 16), 2);
+        assert_eq!(number_of_digits(256, 16), 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "is not made of base 8 numbers")]
+    fn radix_parsing_rejects_digits() {
+        parse_range("17-19", 8);
+    }
+
+    #[test]
+    fn radix_out_of_range() {
+        for radix in [0, 1, 37] {
+            let message = format!("Radix must be between 2 and 36, not {}", radix);
+            let checks: [fn(u32); 4] = [
+                |radix| _ = parse_range("10-20", radix),
+                |radix| _ = RangeReport::new(10, 20, radix, false),
+                |radix| _ = InvalidIds::in_range(10, 20, radix),
+                |radix| _ = InvalidId::new(1, 2, 20, radix),
+            ];
+            for check in checks {
+                let panic = std::panic::catch_unwind(|| check(radix)).unwrap_err();
+                assert_eq!(panic.downcast_ref::<String>(), Some(&message));
+            }
+        }
+    }
+
+    #[test]
+    fn other_radixes_match_brute_force() {
+        let ranges = [(1, 1), (1, 5000), (3, 70000), (4095, 4096), (30000, 65535)];
+        for radix in [2, 3, 8, 16, 36] {
+            for (start, end) in ranges {
+                let expected = brute_force(start, end, radix);
+                assert_eq!(sums(start, end, radix), expected, "{}-{} base {}", start, end, radix);
+                let iterated: u128 = InvalidIds::in_range(start, end, radix).map(|i| i.id).sum();
+                assert_eq!(iterated, expected.1, "{}-{} base {}", start, end, radix);
+            }
+        }
+        // 0b101101 is 101 twice and 0b111111 is 1 six times.
+        let found: Vec<(u128, u128, u32)> = InvalidIds::in_range(0b101100, 0b111111, 2)
+            .map(|invalid_id| (invalid_id.id, invalid_id.pattern, invalid_id.repeats))
+            .collect();
+        assert_eq!(found, vec![(0b101101, 0b101, 2), (0b110110, 0b110, 2), (0b111111, 1, 6)]);
+    }
+
+    #[test]
+    fn decimal_matches_brute_force() {
+        assert_eq!(sums(1, 200000, 10), brute_force(1, 200000, 10));
+    }
+
+    #[test]
+    fn wide_ranges() {
+        // Every ten digit pattern of one repeated digit: 1111111111 + ... + 9999999999.
+        let one_digit: u128 = (1..=9).map(|d| d * 1111111111).sum();
+        let (_, total) = sums(1111111111, 9999999999, 10);
+        let (_, total_without_one_digit) = sums(1111111112, 9999999998, 10);
+        assert_eq!(total - total_without_one_digit, 1111111111 + 9999999999);
+        assert!(total > one_digit);
+        assert_eq!(sums(1, 99999999999, 10).0, (1..=99999).map(|p: u128| {
+            let digits = number_of_digits(p, 10);
+            p * (10_u128.pow(digits) + 1)
+        }).sum());
+    }
+
+    #[test]
+    fn range_reports() {
+        let reports = report_file("inputs/example.txt", 10, true).unwrap();
+        assert_eq!(reports.len(), 11);
+        assert_eq!(reports[1].ids, Some(vec![99, 111]));
+        assert_eq!(reports[1].total.count, 2);
+        assert_eq!(to_u128(&reports[1].total.sum), 210);
+        assert_eq!(reports[1].part_1().count, 1);
+        assert_eq!(reports[1].by_repeats.keys().collect::<Vec<_>>(), vec![&2, &3]);
+
+        let (part_1, total) = parse_file("inputs/example.txt", 10, false).unwrap();
+        let part_1_sum: u128 = reports.iter().map(|r| to_u128(&r.part_1().sum)).sum();
+        let total_sum: u128 = reports.iter().map(|r| to_u128(&r.total.sum)).sum();
+        assert_eq!((part_1_sum, total_sum), (to_u128(&part_1), to_u128(&total)));
+    }
+
+    #[test]
+    fn report_by_repeats() {
+        // 111111 has a one digit pattern, so it is filed under 6 repeats but still counts for part 1.
+        let report = RangeReport::new(100000, 999999, 10, false).unwrap();
+        let ids: Vec<InvalidId> = InvalidIds::in_range(100000, 999999, 10).collect();
+        for repeats in [2, 3, 6] {
+            let matching: Vec<u128> = ids.iter().filter(|i| i.repeats == repeats).map(|i| i.id).collect();
+            let tally = &report.by_repeats[&repeats];
+            assert_eq!(tally.count, matching.len() as u128);
+            assert_eq!(to_u128(&tally.sum), matching.iter().sum::<u128>());
+        }
+        assert_eq!(report.by_repeats.len(), 3);
+        assert_eq!(report.part_1().count, 900);
+        assert_eq!(report.total.count, ids.len() as u128);
+        assert_eq!(report.ids, None);
+
+        let printed = RangeReport::new(95, 115, 10, true).unwrap().to_string();
+        assert_eq!(printed, "95-115: 2 invalid IDs, sum 210 (part 1: 1 IDs, sum 99)\n  \
+            repeated 2 times: 1 IDs, sum 99\n  repeated 3 times: 1 IDs, sum 111\n  IDs: 99, 111\n");
+    }
+
+    #[test]
+    fn parse_policies() {
+        assert_eq!("exactly:3".parse(), Ok(RepeatPolicy::new(RepeatCounts::Exactly(3), Reading::AnyDivisor)));
+        assert_eq!("at-least:4".parse(), Ok(RepeatPolicy::new(RepeatCounts::AtLeast(4), Reading::AnyDivisor)));
+        let one_of = RepeatPolicy::new(RepeatCounts::OneOf(vec![2, 5, 7]), Reading::AnyDivisor);
+        assert_eq!("one-of:2, 5,7".parse(), Ok(one_of));
+        assert_eq!("prime".parse(), Ok(RepeatPolicy::new(RepeatCounts::Prime, Reading::AnyDivisor)));
+        assert_eq!("shortest:prime".parse(), Ok(RepeatPolicy::new(RepeatCounts::Prime, Reading::Shortest)));
+        assert_eq!("shortest:exactly:6".parse(), Ok(RepeatPolicy::new(RepeatCounts::Exactly(6), Reading::Shortest)));
+        assert!("exactly:".parse::<RepeatPolicy>().is_err());
+        assert!("sometimes".parse::<RepeatPolicy>().is_err());
+        assert!("shortest:".parse::<RepeatPolicy>().is_err());
+    }
+
+    #[test]
+    fn policy_matches() {
+        let any = |counts| RepeatPolicy::new(counts, Reading::AnyDivisor);
+        let shortest = |counts| RepeatPolicy::new(counts, Reading::Shortest);
+        // 111111 repeats 6 times, so it can also be read as 2 and 3 repeats, but never as 4.
+        assert!(any(RepeatCounts::Exactly(2)).matches(6));
+        assert!(any(RepeatCounts::Exactly(6)).matches(6));
+        assert!(!any(RepeatCounts::Exactly(4)).matches(6));
+        assert!(!any(RepeatCounts::Exactly(2)).matches(9));
+        assert!(any(RepeatCounts::AtLeast(4)).matches(6));
+        assert!(!any(RepeatCounts::AtLeast(4)).matches(3));
+        assert!(any(RepeatCounts::OneOf(vec![3, 5])).matches(15));
+        assert!(!any(RepeatCounts::OneOf(vec![4, 5])).matches(6));
+        assert!(any(RepeatCounts::Prime).matches(4));
+        assert!(!any(RepeatCounts::Exactly(1)).matches(1));
+        // Read by the shortest pattern, 111111 is 6 repeats only.
+        assert!(!shortest(RepeatCounts::Exactly(2)).matches(6));
+        assert!(shortest(RepeatCounts::Exactly(6)).matches(6));
+        assert!(!shortest(RepeatCounts::AtLeast(7)).matches(6));
+        assert!(shortest(RepeatCounts::Prime).matches(5));
+        assert!(!shortest(RepeatCounts::Prime).matches(4));
+        assert!(!shortest(RepeatCounts::Prime).matches(1));
+        assert!(!shortest(RepeatCounts::Exactly(1)).matches(1));
+    }
+
+    #[test]
+    fn policies_match_brute_force() {
+        let counts = [
+            RepeatCounts::Exactly(2),
+            RepeatCounts::Exactly(3),
+            RepeatCounts::Exactly(6),
+            RepeatCounts::AtLeast(3),
+            RepeatCounts::OneOf(vec![3, 4]),
+            RepeatCounts::Prime,
+        ];
+        // Whether a repeat count is one of those asked for, written out separately from allows.
+        let allowed = |counts: &RepeatCounts, repeats: usize| match counts {
+            RepeatCounts::Exactly(k) => repeats == *k as usize,
+            RepeatCounts::AtLeast(k) => repeats >= *k as usize,
+            RepeatCounts::OneOf(ks) => ks.iter().any(|&k| k as usize == repeats),
+            RepeatCounts::Prime => repeats > 1 && (2..repeats).all(|d| !repeats.is_multiple_of(d)),
+        };
+        for radix in [2, 10] {
+            let report = RangeReport::new(1, 100000, radix, false).unwrap();
+            for counts in &counts {
+                let mut any_divisor = Tally::default();
+                let mut shortest = Tally::default();
+                for value in 1..=100000_u128 {
+                    let digits: Vec<char> = to_radix_string(value, radix).chars().collect();
+                    // The pattern sizes the digits are made of, repeated at least twice, shortest first.
+                    let pattern_sizes: Vec<usize> = (1..digits.len())
+                        .filter(|&size| {
+                            digits.len().is_multiple_of(size) && digits.chunks(size).all(|c| c == &digits[..size])
+                        })
+                        .collect();
+                    let add = |tally: &mut Tally| {
+                        tally.count += 1;
+                        tally.sum += Total::from(value);
+                    };
+                    // Invalid if the digits are some pattern repeated an allowed number of times.
+                    if pattern_sizes.iter().any(|&size| allowed(counts, digits.len() / size)) {
+                        add(&mut any_divisor);
+                    }
+                    // Invalid if the shortest pattern the digits are made of repeats an allowed
+                    // number of times.
+                    if pattern_sizes.first().is_some_and(|&size| allowed(counts, digits.len() / size)) {
+                        add(&mut shortest);
+                    }
+                }
+                let policy = RepeatPolicy::new(counts.clone(), Reading::AnyDivisor);
+                assert_eq!(report.tally(&policy), any_divisor, "{:?} base {}", policy, radix);
+                let policy = RepeatPolicy::new(counts.clone(), Reading::Shortest);
+                assert_eq!(report.tally(&policy), shortest, "{:?} base {}", policy, radix);
+            }
+        }
+        // 111111 is invalid for part 2, but its shortest pattern repeats 6 times, which is not prime.
+        let report = RangeReport::new(111111, 111111, 10, false).unwrap();
+        assert_eq!(report.tally(&RepeatPolicy::part_2()).count, 1);
+        assert_eq!(report.tally(&RepeatPolicy::new(RepeatCounts::Prime, Reading::AnyDivisor)).count, 1);
+        assert_eq!(report.tally(&RepeatPolicy::new(RepeatCounts::Prime, Reading::Shortest)).count, 0);
+        let report = RangeReport::new(1, 10_u128.pow(12), 10, false).unwrap();
+        assert!(report.tally(&RepeatPolicy::new(RepeatCounts::Prime, Reading::Shortest)).count < report.total.count);
+    }
+
+    #[test]
+    fn sum_file_with_policies() {
+        let sums = sum_file("inputs/example.txt", 10, &[RepeatPolicy::part_1(), RepeatPolicy::part_2()], false).unwrap();
+        assert_eq!(sums, vec![Total::from(1227775554_u128), Total::from(4174379265_u128)]);
+        // The example IDs that can be read as a pattern repeated three times.
+        let policy = RepeatPolicy::new(RepeatCounts::Exactly(3), Reading::AnyDivisor);
+        let sums = sum_file("inputs/example.txt", 10, &[policy], false);
+        assert_eq!(sums.unwrap(), vec![Total::from(111_u128 + 999 + 222222 + 565656 + 824824824)]);
+        // Going by the shortest pattern, 222222 is 2 repeated six times and drops out.
+        let policy = RepeatPolicy::new(RepeatCounts::Exactly(3), Reading::Shortest);
+        let sums = sum_file("inputs/example.txt", 10, &[policy], false);
+        assert_eq!(sums.unwrap(), vec![Total::from(111_u128 + 999 + 565656 + 824824824)]);
+    }
+
+    fn input_ranges(text: &str) -> Vec<InputRange> {
+        text.lines()
+            .enumerate()
+            .flat_map(|(line, ranges)| {
+                ranges.split(',').enumerate().map(move |(index, range)| {
+                    let (start, end) = parse_range(range.trim(), 10);
+                    InputRange { start, end, text: range.trim().to_string(), line: line + 1, index: index + 1 }
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn read_range_positions() {
+        let ranges = read_ranges("inputs/example.txt", 10);
+        assert_eq!(ranges.len(), 11);
+        assert_eq!(ranges[2], InputRange { start: 998, end: 1012, text: "998-1012".to_string(), line: 1, index: 3 });
+        assert_eq!(ranges[2].to_string(), "998-1012 (line 1, range 3)");
+        assert_eq!(validate_ranges(&ranges), vec![]);
+    }
+
+    #[test]
+    fn validate_range_list() {
+        let ranges = input_ranges("10-20,50-10,015-30\n40-45,10-20,18-25\n1-5,2-3,10-20");
+        let issues = validate_ranges(&ranges);
+        assert_eq!(issues, vec![
+            RangeIssue::Reversed(ranges[1].clone()),
+            RangeIssue::LeadingZeros(ranges[2].clone()),
+            RangeIssue::Overlap { range: ranges[2].clone(), other: ranges[0].clone() },
+            RangeIssue::Duplicate { range: ranges[4].clone(), first: ranges[0].clone() },
+            RangeIssue::Overlap { range: ranges[5].clone(), other: ranges[2].clone() },
+            RangeIssue::Overlap { range: ranges[7].clone(), other: ranges[6].clone() },
+            RangeIssue::Duplicate { range: ranges[8].clone(), first: ranges[0].clone() },
+        ]);
+        assert_eq!(issues[0].to_string(), "50-10 (line 1, range 2) ends before it starts");
+        assert_eq!(issues[3].to_string(), "10-20 (line 2, range 2) duplicates 10-20 (line 1, range 1)");
+        assert_eq!(issues[4].to_string(), "18-25 (line 2, range 3) overlaps 015-30 (line 1, range 3)");
+    }
+
+    #[test]
+    fn merge_overlapping_ranges() {
+        let ranges = input_ranges("10-20,50-10,15-30\n40-45,10-20,31-35\n1-5,2-3");
+        assert_eq!(merge_ranges(&ranges), vec![(1, 5), (10, 35), (40, 45)]);
+        assert_eq!(merge_ranges(&input_ranges(&format!("0-5,6-{}", u128::MAX))), vec![(0, u128::MAX)]);
+    }
+
+    #[test]
+    fn merged_ranges_count_ids_once() {
+        // 1010 and 1111 fall into both ranges, and 2020 is in the second range only.
+        let ranges = input_ranges("1000-1200,1000-2100");
+        let merged = merge_ranges(&ranges);
+        assert_eq!(merged, vec![(1000, 2100)]);
+        let merged_sum = sums(1000, 2100, 10).1;
+        let unmerged_sum = sums(1000, 1200, 10).1 + sums(1000, 2100, 10).1;
+        assert_eq!(unmerged_sum - merged_sum, 1010 + 1111);
+    }
+
+    #[test]
+    fn ids_beyond_64_bits() {
+        // Every 20 digit number that is a 10 digit pattern twice, and nothing else repeats twice.
+        let patterns_sum = (1_000_000_000 + 9_999_999_999) * 9_000_000_000 / 2;
+        assert_eq!(sums(10_u128.pow(19), 10_u128.pow(20) - 1, 10).0, 10_000_000_001 * patterns_sum);
+
+        let start = 12345678901234567890123456789012345678;
+        let end = start + 10_u128.pow(20);
+        // 38 digit IDs are a 19 digit pattern twice, one every 10^19 + 1.
+        let iterated: Vec<u128> = InvalidIds::in_range(start, end, 10).map(|i| i.id).collect();
+        assert_eq!(iterated.len(), 10);
+        assert_eq!(iterated[0], 12345678901234567891234567890123456789);
+        let iterated_sum: u128 = iterated.iter().sum();
+        assert_eq!(sums(start, end, 10), (iterated_sum, iterated_sum));
+
+        let found: Vec<(u128, u128, u32)> = InvalidIds::in_range(u128::MAX - 1, u128::MAX, 2)
+            .map(|invalid_id| (invalid_id.id, invalid_id.pattern, invalid_id.repeats))
+            .collect();
+        assert_eq!(found, vec![(u128::MAX, 1, 128)]);
+    }
+
+    #[test]
+    fn invalid_id_past_u128_expires() {
+        let invalid_id = InvalidId::new(10_u128.pow(19), 2, u128::MAX, 10);
+        assert!(invalid_id.expired);
+        let invalid_id = InvalidId::new(u64::MAX as u128, 2, u128::MAX, 2);
+        assert_eq!(invalid_id.id, u128::MAX);
+        assert!(!invalid_id.expired);
+    }
+
+    #[test]
+    #[should_panic(expected = "does not fit in 128 bits")]
+    fn range_past_u128() {
+        parse_range("1-1000000000000000000000000000000000000000", 10);
+    }
+
+    #[cfg(not(feature = "bigint"))]
+    #[test]
+    fn overflow_is_reported() {
+        assert_eq!(sum_invalid_ids(0, u128::MAX, 2), None);
+        assert!(sum_invalid_ids(0, 10_u128.pow(24), 10).is_some());
+    }
+
+    #[cfg(feature = "bigint")]
+    #[test]
+    fn big_sums_do_not_overflow() {
+        let (part_1, total) = sum_invalid_ids(0, u128::MAX, 2).unwrap();
+        assert!(part_1 > Total::from(u128::MAX));
+        assert!(total > part_1);
+        // The 128 bit patterns that repeat twice are every 64 bit pattern with its top bit set.
+        let patterns = (1_u128 << 63)..=(u64::MAX as u128);
+        let multiplier = Total::from((1_u128 << 64) + 1);
+        let patterns_sum = (Total::from(*patterns.start()) + Total::from(*patterns.end()))
+            * Total::from(1_u128 << 63) / Total::from(2_u128);
+        let (part_1_128_bits, _) = sum_invalid_ids(1 << 127, u128::MAX, 2).unwrap();
+        assert_eq!(part_1_128_bits, multiplier * patterns_sum);
+    }
 }
@@ -1,7 +1,418 @@
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::cmp::{Ordering, Reverse};
+use std::io::{BufRead, BufReader, IsTerminal};
+use std::str::FromStr;
+use std::{fmt, ops};
 
-fn parse_line(line: &str, batteries: i32) -> i64 {
+// A joltage of any number of digits, kept as its digits in the bank's radix (most significant
+// first, without leading zeros) so selecting more batteries than fit in an integer can't overflow.
+#[derive(Clone, PartialEq)]
+struct Joltage {
+    digits: Vec<u8>,
+    radix: u32,
+}
+
+impl Joltage {
+    fn zero(radix: u32) -> Self {
+        Joltage { digits: vec![], radix }
+    }
+
+    fn from_digits(digits: &[i64], radix: u32) -> Self {
+        let digits: Vec<u8> = digits.iter().map(|&digit| digit as u8).skip_while(|&digit| digit == 0).collect();
+        Joltage { digits, radix }
+    }
+}
+
+impl From<u64> for Joltage {
+    fn from(value: u64) -> Self {
+        let digits: Vec<i64> = value.to_string().chars().map(|c| c.to_digit(10).unwrap() as i64).collect();
+        Joltage::from_digits(&digits, 10)
+    }
+}
+
+impl ops::AddAssign<&Joltage> for Joltage {
+    fn add_assign(&mut self, rhs: &Joltage) {
+        assert_eq!(self.radix, rhs.radix, "Cannot add joltages of different radixes");
+        let radix = self.radix as u8;
+        let length = self.digits.len().max(rhs.digits.len());
+        let mut sum: Vec<u8> = Vec::with_capacity(length + 1);
+        let mut carry = 0;
+        let mut lhs_digits = self.digits.iter().rev();
+        let mut rhs_digits = rhs.digits.iter().rev();
+        for _ in 0..length {
+            let digit = lhs_digits.next().unwrap_or(&0) + rhs_digits.next().unwrap_or(&0) + carry;
+            sum.push(digit % radix);
+            carry = digit / radix;
+        }
+        if carry > 0 {
+            sum.push(carry);
+        }
+        sum.reverse();
+        self.digits = sum;
+    }
+}
+
+impl PartialEq<u64> for Joltage {
+    fn eq(&self, other: &u64) -> bool {
+        let value = self.digits.iter().try_fold(0_u64, |acc, &digit| acc.checked_mul(self.radix as u64)?.checked_add(digit as u64));
+        value == Some(*other)
+    }
+}
+
+impl fmt::Display for Joltage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.digits.is_empty() {
+            return write!(f, "0");
+        }
+        self.digits
+            .iter()
+            .try_for_each(|&digit| write!(f, "{}", char::from_digit(digit as u32, self.radix).unwrap()))
+    }
+}
+
+impl fmt::Debug for Joltage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+// Picks the batteries that give the largest joltage and returns their positions in the bank.
+// Keeping the largest k-subsequence is the same as removing n - k digits greedily: while there are
+// digits left to remove and the last kept digit is smaller than the next one, dropping it makes the
+// number larger. Every digit is pushed and popped at most once, so this is O(n).
+fn select_batteries(bank: &[i64], batteries: usize) -> Vec<usize> {
+    stack_select(bank, batteries, |kept, next| kept < next)
+}
+
+// The smallest k-subsequence, by the same greedy removal with the comparison flipped.
+fn select_smallest(bank: &[i64], batteries: usize) -> Vec<usize> {
+    stack_select(bank, batteries, |kept, next| kept > next)
+}
+
+// Removes n - k digits from the bank, dropping the last kept digit whenever `replace(kept, next)`.
+fn stack_select(bank: &[i64], batteries: usize, replace: impl Fn(i64, i64) -> bool) -> Vec<usize> {
+    let mut to_remove = bank.len() - batteries;
+    let mut chosen: Vec<usize> = Vec::with_capacity(batteries);
+    for (i, &joltage) in bank.iter().enumerate() {
+        while to_remove > 0 && chosen.last().is_some_and(|&last| replace(bank[last], joltage)) {
+            chosen.pop();
+            to_remove -= 1;
+        }
+        // A full selection can't take a digit that didn't beat the last kept one, so it is dropped.
+        if chosen.len() < batteries {
+            chosen.push(i);
+        } else {
+            to_remove -= 1;
+        }
+    }
+    chosen
+}
+
+// What a selection of batteries should optimize.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Objective {
+    // The largest joltage, as in the puzzle.
+    Maximum,
+    // The smallest joltage whose first digit is not zero.
+    Minimum,
+    // The largest joltage with at least this many batteries left off between two chosen ones.
+    MaximumWithGap(usize),
+    // The largest joltage when the end of the bank wraps around to its start, so the chosen
+    // batteries may be read starting anywhere.
+    MaximumCircular,
+}
+
+// Parses "max", "min", "gap:<g>" or "circular".
+impl FromStr for Objective {
+    type Err = String;
+
+    fn from_str(objective: &str) -> Result<Self, Self::Err> {
+        match objective.split_once(':') {
+            None if objective == "max" => Ok(Objective::Maximum),
+            None if objective == "min" => Ok(Objective::Minimum),
+            None if objective == "circular" => Ok(Objective::MaximumCircular),
+            Some(("gap", gap)) => gap
+                .parse()
+                .map(Objective::MaximumWithGap)
+                .map_err(|_| format!("Invalid gap ({})", gap)),
+            _ => Err(format!("Unknown objective ({})", objective)),
+        }
+    }
+}
+
+// The positions of the chosen batteries, in the order their digits are read, or None if the bank
+// has no selection that satisfies the objective.
+fn select_with(bank: &[i64], batteries: usize, objective: Objective) -> Option<Vec<usize>> {
+    if batteries > bank.len() {
+        return None;
+    }
+    match objective {
+        Objective::Maximum => Some(select_batteries(bank, batteries)),
+        Objective::Minimum => select_minimum(bank, batteries),
+        Objective::MaximumWithGap(gap) => select_with_gap(bank, batteries, gap),
+        Objective::MaximumCircular => Some(select_circular(bank, batteries)),
+    }
+}
+
+// The first digit is the smallest non-zero digit that still leaves room for the rest, taken as
+// early as possible so the most digits remain to choose from. The rest is then the smallest
+// subsequence of what follows, where zeros are welcome.
+fn select_minimum(bank: &[i64], batteries: usize) -> Option<Vec<usize>> {
+    if batteries == 0 {
+        return Some(vec![]);
+    }
+    let first = (0..=bank.len() - batteries)
+        .filter(|&i| bank[i] != 0)
+        .min_by_key(|&i| (bank[i], i))?;
+    let mut chosen = vec![first];
+    chosen.extend(select_smallest(&bank[first + 1..], batteries - 1).iter().map(|i| first + 1 + i));
+    Some(chosen)
+}
+
+// Each chosen battery is the largest (and earliest, on ties) digit between `gap` past the previous
+// choice and the last position that still leaves room for the remaining batteries and their gaps.
+// A sparse table answers each of those range maximum queries in O(1) after O(n log n) setup.
+fn select_with_gap(bank: &[i64], batteries: usize, gap: usize) -> Option<Vec<usize>> {
+    if batteries == 0 {
+        return Some(vec![]);
+    }
+    let span = (batteries - 1).checked_mul(gap + 1)?.checked_add(1)?;
+    if span > bank.len() {
+        return None;
+    }
+
+    let better = |a: usize, b: usize| if bank[b] > bank[a] || (bank[b] == bank[a] && b < a) { b } else { a };
+    let mut table: Vec<Vec<usize>> = vec![(0..bank.len()).collect()];
+    let mut width = 1;
+    while 2 * width <= bank.len() {
+        let previous = table.last().unwrap();
+        let level = (0..=bank.len() - 2 * width).map(|i| better(previous[i], previous[i + width])).collect();
+        table.push(level);
+        width *= 2;
+    }
+    let range_max = |lo: usize, hi: usize| {
+        let level = (hi - lo + 1).ilog2() as usize;
+        better(table[level][lo], table[level][hi + 1 - (1 << level)])
+    };
+
+    let mut chosen: Vec<usize> = Vec::with_capacity(batteries);
+    let mut lo = 0;
+    for remaining in (0..batteries).rev() {
+        let hi = bank.len() - 1 - remaining * (gap + 1);
+        let i = range_max(lo, hi);
+        chosen.push(i);
+        lo = i + gap + 1;
+    }
+    Some(chosen)
+}
+
+// Reading can start anywhere, so the first digit is the largest digit in the bank, and the reading
+// starts at one of its occurrences. From such a start, the monotonic stack spends its n - k removals
+// on the runs of smaller digits between occurrences in order: each run is removed whole by the next
+// occurrence, until the removals left are fewer than the next run. That run loses its smallest
+// digits and everything after it is kept as it is. So a start reads as the largest digit once more
+// than the runs it removed, then the trimmed run, then the rest of the circle, and the best start:
+// - has the most runs removed whole, found for every start at once with two pointers;
+// - then has the best trimmed run, where a shorter run that is a prefix of a longer one wins, since
+//   the largest digit comes next. Each start trims a different run, so this is O(n) overall;
+// - then has the best rest of the circle, which all have the same length, so the start whose rest
+//   begins the largest rotation wins. Rotations are only ranked if starts are still tied.
+fn select_circular(bank: &[i64], batteries: usize) -> Vec<usize> {
+    if batteries == 0 {
+        return vec![];
+    }
+    let n = bank.len();
+    let largest = *bank.iter().max().unwrap();
+    let occurrences: Vec<usize> = (0..n).filter(|&i| bank[i] == largest).collect();
+    let count = occurrences.len();
+    if count >= batteries {
+        return occurrences[..batteries].to_vec();
+    }
+    // The t-th occurrence counting on around the circle, as a position in the doubled bank.
+    let position = |t: usize| occurrences[t % count] + t / count * n;
+    let run = |t: usize| position(t + 1) - position(t) - 1;
+
+    // Runs removed whole when starting at each occurrence, and the digits they take up. Fewer than
+    // `batteries` occurrences means the runs add up to more than the removals, so none wraps all
+    // the way around.
+    let removals = n - batteries;
+    let mut removed: Vec<(usize, usize)> = Vec::with_capacity(count);
+    let (mut end, mut used) = (0, 0);
+    for start in 0..count {
+        end = end.max(start);
+        while used + run(end) <= removals {
+            used += run(end);
+            end += 1;
+        }
+        removed.push((end - start, used));
+        if end > start {
+            used -= run(start);
+        }
+    }
+    let most = removed.iter().map(|&(runs, _)| runs).max().unwrap();
+
+    // The positions kept from the trimmed run, in the doubled bank.
+    let doubled: Vec<i64> = bank.repeat(2);
+    let trim = |start: usize| {
+        let first = position(start + most) + 1;
+        let length = run(start + most);
+        let kept = select_batteries(&doubled[first..first + length], length - (removals - removed[start].1));
+        kept.into_iter().map(|i| first + i).collect::<Vec<usize>>()
+    };
+    let mut best: Vec<usize> = vec![];
+    let mut tied: Vec<(usize, Vec<usize>)> = vec![];
+    for start in (0..count).filter(|&start| removed[start].0 == most) {
+        let kept = trim(start);
+        let order = kept
+            .iter()
+            .zip(&best)
+            .map(|(&i, &j)| doubled[i].cmp(&doubled[j]))
+            .find(|order| order.is_ne())
+            .unwrap_or(best.len().cmp(&kept.len()));
+        if tied.is_empty() || order == Ordering::Greater {
+            best = kept.clone();
+            tied.clear();
+        }
+        if tied.is_empty() || order != Ordering::Less {
+            tied.push((start, kept));
+        }
+    }
+    let ranks = if tied.len() > 1 { rotation_ranks(bank) } else { vec![0; n] };
+    let (start, kept) = tied.into_iter().max_by_key(|(start, _)| (ranks[position(start + most + 1) % n], Reverse(*start))).unwrap();
+
+    let mut chosen: Vec<usize> = (start..=start + most).map(|t| position(t) % n).collect();
+    chosen.extend(kept.iter().map(|i| i % n));
+    chosen.extend((position(start + most + 1)..position(start + count)).map(|i| i % n));
+    chosen
+}
+
+// The rank of every rotation of the bank among all of its rotations, by prefix doubling: once the
+// rotations are ranked by their first `length` digits, a pair of those ranks orders them by their
+// first 2 * length digits. O(n log^2 n), stopping early once every rank is distinct.
+fn rotation_ranks(bank: &[i64]) -> Vec<usize> {
+    let n = bank.len();
+    let mut ranks: Vec<usize> = bank.iter().map(|&digit| digit as usize).collect();
+    let mut order: Vec<usize> = (0..n).collect();
+    let mut length = 1;
+    while length < n {
+        let key = |i: usize| (ranks[i], ranks[(i + length) % n]);
+        order.sort_unstable_by_key(|&i| key(i));
+        let mut next = vec![0; n];
+        for w in order.windows(2) {
+            next[w[1]] = next[w[0]] + usize::from(key(w[1]) != key(w[0]));
+        }
+        let distinct = next[order[n - 1]] + 1;
+        ranks = next;
+        if distinct == n {
+            break;
+        }
+        length *= 2;
+    }
+    ranks
+}
+
+// The batteries switched on in a bank: their positions, in order, and their joltage digits.
+#[derive(Debug, PartialEq)]
+struct Selection {
+    indices: Vec<usize>,
+    digits: Vec<i64>,
+    radix: u32,
+}
+
+impl Selection {
+    fn joltage(&self) -> Joltage {
+        Joltage::from_digits(&self.digits, self.radix)
+    }
+
+    // The bank with every chosen battery highlighted, in bold green when writing to a terminal and
+    // in square brackets otherwise.
+    fn highlight(&self, line: &str, color: bool) -> String {
+        let mut switched_on = vec![false; line.chars().count()];
+        for &i in &self.indices {
+            switched_on[i] = true;
+        }
+        let mut out = String::new();
+        for (i, c) in line.chars().enumerate() {
+            if !switched_on[i] {
+                out.push(c);
+            } else if color {
+                out.push_str(&format!("\x1b[1;32m{}\x1b[0m", c));
+            } else {
+                out.push_str(&format!("[{}]", c));
+            }
+        }
+        out
+    }
+}
+
+// Why a bank could not be used.
+#[derive(Debug, PartialEq)]
+enum BankIssue {
+    // A character that is not a digit in the bank's radix, at a 1-based column.
+    InvalidDigit { column: usize, character: char },
+    TooShort { length: usize, batteries: usize },
+    // The bank is long enough, but no choice of batteries satisfies the objective.
+    NoSelection(Objective),
+}
+
+impl fmt::Display for BankIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BankIssue::InvalidDigit { column, character } => {
+                write!(f, "{:?} at column {} is not a digit", character, column)
+            }
+            BankIssue::TooShort { length, batteries } => {
+                write!(f, "bank of {} batteries is too short to switch on {}", length, batteries)
+            }
+            BankIssue::NoSelection(objective) => write!(f, "no selection of batteries for {:?}", objective),
+        }
+    }
+}
+
+// A bank issue together with where it was found in the input.
+#[derive(Debug, PartialEq)]
+struct BankError {
+    line: usize,
+    bank: String,
+    issue: BankIssue,
+}
+
+impl fmt::Display for BankError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Line {} ({}): {}", self.line, self.bank, self.issue)
+    }
+}
+
+fn parse_bank(line: &str, radix: u32) -> Result<Vec<i64>, BankIssue> {
+    line.chars()
+        .enumerate()
+        .map(|(i, c)| {
+            c.to_digit(radix)
+                .map(|digit| digit as i64)
+                .ok_or(BankIssue::InvalidDigit { column: i + 1, character: c })
+        })
+        .collect()
+}
+
+fn select_line(line: &str, batteries: usize, objective: Objective, radix: u32) -> Result<Selection, BankIssue> {
+    let bank_int = parse_bank(line, radix)?;
+    if bank_int.len() < batteries {
+        return Err(BankIssue::TooShort { length: bank_int.len(), batteries });
+    }
+
+    let indices = select_with(&bank_int, batteries, objective).ok_or(BankIssue::NoSelection(objective))?;
+    let digits = indices.iter().map(|&i| bank_int[i]).collect();
+    Ok(Selection { indices, digits, radix })
+}
+
+#[cfg(test)]
+fn parse_line(line: &str, batteries: usize) -> Joltage {
+    select_line(line, batteries, Objective::Maximum, 10).unwrap().joltage()
+}
+
+// The selection select_batteries replaced, which rescans the chosen batteries for every digit.
+#[cfg(test)]
+fn parse_line_rescan(line: &str, batteries: i32) -> i64 {
     let bank_int: Vec<i64> = line.chars().map(|c| c.to_digit(10).expect("Expected a digit") as i64).collect::<Vec<i64>>();
 
     let mut chosen_batteries = vec![];
@@ -39,25 +450,101 @@ fn parse_line(line: &str, batteries: i32) -> i64 {
     joltage
 }
 
-fn parse_file(filename: &str, batteries: i32) -> i64 {
+// Sums the joltages of every bank, or reports the first bank that can't be used.
+fn parse_file(filename: &str, batteries: usize, objective: Objective, radix: u32) -> Result<Joltage, BankError> {
+    let file = File::open(filename).expect("Could not open file");
+    let reader = BufReader::new(file);
+
+    let mut sum = Joltage::zero(radix);
+    for (line_index, line) in reader.lines().enumerate() {
+        let line = line.expect("Could not read line");
+        match select_line(&line, batteries, objective, radix) {
+            Ok(selection) => sum += &selection.joltage(),
+            Err(issue) => return Err(BankError { line: line_index + 1, bank: line, issue }),
+        }
+    }
+    Ok(sum)
+}
+
+// Prints every bank with the chosen batteries highlighted, followed by its joltage.
+fn show_file(filename: &str, batteries: usize, objective: Objective, radix: u32) {
     let file = File::open(filename).expect("Could not open file");
     let reader = BufReader::new(file);
+    let color = std::io::stdout().is_terminal();
 
-    let mut sum = 0;
     for line in reader.lines() {
-        sum += parse_line(&line.expect("Could not read line"), batteries);
+        let line = line.expect("Could not read line");
+        match select_line(&line, batteries, objective, radix) {
+            Ok(selection) => println!("{} {}", selection.highlight(&line, color), selection.joltage()),
+            Err(issue) => println!("{} - {}", line, issue),
+        }
+    }
+}
+
+fn main() {
+    // "--show <batteries>" prints each bank with the batteries that were switched on.
+    // "--objective <objective>" picks the batteries by another objective than the largest joltage.
+    // "--radix <n>" reads the banks as base n digits, so "--radix 16" accepts hex-digit banks.
+    let mut args = std::env::args().skip(1);
+    let mut show: Option<usize> = None;
+    let mut objective = Objective::Maximum;
+    let mut radix = 10;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--radix" => {
+                radix = args.next().and_then(|r| r.parse().ok()).expect("Expected a radix after --radix");
+                if !(2..=36).contains(&radix) {
+                    panic!("Radix must be between 2 and 36");
+                }
+            }
+            "--show" => show = Some(args.next().and_then(|b| b.parse().ok()).expect("Expected a number of batteries")),
+            "--objective" => {
+                let value = args.next().expect("Expected an objective after --objective");
+                objective = value.parse().unwrap_or_else(|error| panic!("{}", error));
+            }
+            _ => panic!("Unknown argument ({})", arg),
+        }
+    }
+
+    if let Some(batteries) = show {
+        show_file("inputs/input.txt", batteries, objective, radix);
+        return;
+    }
+
+    let joltages = parse_file("inputs/input.txt", 2, objective, radix)
+        .and_then(|joltage_1| Ok((joltage_1, parse_file("inputs/input.txt", 12, objective, radix)?)));
+    match joltages {
+        Ok((joltage_1, joltage_2)) => {
+            println!("Total joltage part 1: {}", joltage_1);
+            println!("Total joltage part 2: {}", joltage_2);
+        }
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
     }
-    sum
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::time::Instant;
+
+    // A reproducible bank of pseudo-random digits.
+    fn random_bank(length: usize, seed: u64) -> String {
+        let mut state = seed;
+        (0..length)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                char::from_digit(((state >> 33) % 9 + 1) as u32, 10).unwrap()
+            })
+            .collect()
+    }
 
     #[test]
     fn example() {
-        assert_eq!(parse_file("inputs/example.txt", 2), 357);
-        assert_eq!(parse_file("inputs/example.txt", 12), 3121910778619);
+        assert_eq!(parse_file("inputs/example.txt", 2, Objective::Maximum, 10).unwrap(), 357);
+        assert_eq!(parse_file("inputs/example.txt", 12, Objective::Maximum, 10).unwrap(), 3121910778619);
     }
 
     #[test]
@@ -69,11 +556,250 @@ mod tests {
         assert_eq!(parse_line("17113779", 4), 7779);
         assert_eq!(parse_line("12345", 3), 345);
     }
-}
 
-fn main() {
-    let joltage_1 = parse_file("inputs/input.txt", 2);
-    let joltage_2 = parse_file("inputs/input.txt", 12);
-    println!("Total joltage part 1: {}", joltage_1);
-    println!("Total joltage part 2: {}", joltage_2);
+    #[test]
+    fn selected_positions() {
+        assert_eq!(select_batteries(&[1, 7, 1, 1, 3, 7, 7, 9], 4), vec![1, 5, 6, 7]);
+        assert_eq!(select_batteries(&[9, 8, 7], 3), vec![0, 1, 2]);
+        assert_eq!(select_batteries(&[5, 5, 5, 5], 2), vec![0, 1]);
+        assert_eq!(select_batteries(&[3, 1, 2], 0), vec![]);
+    }
+
+    #[test]
+    fn line_selection() {
+        let selection = select_line("818181911112111", 12, Objective::Maximum, 10).unwrap();
+        assert_eq!(selection.indices, vec![0, 2, 4, 6, 7, 8, 9, 10, 11, 12, 13, 14]);
+        assert_eq!(selection.digits, vec![8, 8, 8, 9, 1, 1, 1, 1, 2, 1, 1, 1]);
+        assert_eq!(selection.joltage(), 888911112111);
+
+        let selection = select_line("17113779", 2, Objective::Maximum, 10).unwrap();
+        // Of equal digits, the earliest one is switched on.
+        assert_eq!(selection, Selection { indices: vec![1, 7], digits: vec![7, 9], radix: 10 });
+        assert_eq!(selection.highlight("17113779", false), "1[7]11377[9]");
+        assert_eq!(selection.highlight("17113779", true), "1\x1b[1;32m7\x1b[0m11377\x1b[1;32m9\x1b[0m");
+    }
+
+    #[test]
+    fn joltage_arithmetic() {
+        let mut sum = Joltage::from(999);
+        sum += &Joltage::from(1);
+        assert_eq!(sum, 1000);
+        sum += &Joltage::from(0);
+        assert_eq!(sum.to_string(), "1000");
+        assert_eq!(Joltage::from(0).to_string(), "0");
+        assert_eq!(Joltage::from_digits(&[0, 0, 4, 2], 10), 42);
+
+        let mut sum = Joltage::from(u64::MAX);
+        sum += &Joltage::from(u64::MAX);
+        assert_eq!(sum.to_string(), "36893488147419103230");
+    }
+
+    #[test]
+    fn long_selections() {
+        // Selecting the whole bank gives back the bank itself.
+        let bank = random_bank(500, 7);
+        assert_eq!(parse_line(&bank, 500).to_string(), bank);
+        assert_eq!(parse_line("98765432109876543210", 19).to_string(), "9876543219876543210");
+        assert_eq!(parse_line("98765432109876543210", 20).to_string(), "98765432109876543210");
+
+        let part_2 = parse_file("inputs/example.txt", 15, Objective::Maximum, 10).unwrap();
+        assert_eq!(part_2.to_string(), (987654321111111_u64 + 811111111111119 + 234234234234278 + 818181911112111).to_string());
+    }
+
+    #[test]
+    fn bank_issues() {
+        assert_eq!(
+            select_line("12a45", 2, Objective::Maximum, 10),
+            Err(BankIssue::InvalidDigit { column: 3, character: 'a' })
+        );
+        assert_eq!(
+            select_line("123", 4, Objective::Maximum, 10),
+            Err(BankIssue::TooShort { length: 3, batteries: 4 })
+        );
+        assert_eq!(
+            select_line("0001", 2, Objective::Minimum, 10),
+            Err(BankIssue::NoSelection(Objective::Minimum))
+        );
+        // Banks as short as the battery count are fine.
+        assert_eq!(parse_line("123", 3), 123);
+
+        let error = parse_file("inputs/example.txt", 16, Objective::Maximum, 10).unwrap_err();
+        assert_eq!(error.line, 1);
+        assert_eq!(error.to_string(), "Line 1 (987654321111111): bank of 15 batteries is too short to switch on 16");
+
+        let path = std::env::temp_dir().join(format!("day_3_bank_issues_{}.txt", std::process::id()));
+        std::fs::write(&path, "12345\n987x5\n").unwrap();
+        let error = parse_file(path.to_str().unwrap(), 2, Objective::Maximum, 10).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(error.to_string(), "Line 2 (987x5): 'x' at column 4 is not a digit");
+    }
+
+    #[test]
+    fn hex_banks() {
+        let selection = select_line("1f3a9b", 3, Objective::Maximum, 16).unwrap();
+        assert_eq!(selection.digits, vec![15, 10, 11]);
+        assert_eq!(selection.joltage().to_string(), "fab");
+        assert_eq!(selection.joltage(), 0xfab);
+        assert_eq!(selection.highlight("1f3a9b", false), "1[f]3[a]9[b]");
+        assert!(select_line("1f3a9b", 3, Objective::Maximum, 10).is_err());
+
+        let mut sum = Joltage::zero(16);
+        sum += &Joltage::from_digits(&[15, 15], 16);
+        sum += &Joltage::from_digits(&[1], 16);
+        assert_eq!(sum.to_string(), "100");
+
+        // Decimal banks read in radix 16 give the same selection, read as hex.
+        let selection = select_line("818181911112111", 12, Objective::Maximum, 16).unwrap();
+        assert_eq!(selection.joltage().to_string(), "888911112111");
+        assert_eq!(selection.joltage(), 0x888911112111);
+    }
+
+    // Tries every choice of batteries (and every starting point, for circular banks) and keeps the
+    // best digits for the objective.
+    fn brute_force(bank: &[i64], batteries: usize, objective: Objective) -> Option<Vec<i64>> {
+        let starts = if objective == Objective::MaximumCircular { bank.len().max(1) } else { 1 };
+        let mut best: Option<Vec<i64>> = None;
+        for start in 0..starts {
+            let rotated: Vec<i64> = bank[start..].iter().chain(&bank[..start]).copied().collect();
+            for mask in 0_u32..1 << bank.len() {
+                if mask.count_ones() as usize != batteries {
+                    continue;
+                }
+                let indices: Vec<usize> = (0..bank.len()).filter(|i| mask & (1 << i) != 0).collect();
+                let digits: Vec<i64> = indices.iter().map(|&i| rotated[i]).collect();
+                let allowed = match objective {
+                    Objective::Minimum => digits.first().is_none_or(|&first| first != 0),
+                    Objective::MaximumWithGap(gap) => indices.windows(2).all(|w| w[1] - w[0] > gap),
+                    _ => true,
+                };
+                let better = match &best {
+                    None => true,
+                    Some(best) if objective == Objective::Minimum => digits < *best,
+                    Some(best) => digits > *best,
+                };
+                if allowed && better {
+                    best = Some(digits);
+                }
+            }
+        }
+        best
+    }
+
+    #[test]
+    fn parse_objectives() {
+        assert_eq!("max".parse(), Ok(Objective::Maximum));
+        assert_eq!("min".parse(), Ok(Objective::Minimum));
+        assert_eq!("gap:3".parse(), Ok(Objective::MaximumWithGap(3)));
+        assert_eq!("circular".parse(), Ok(Objective::MaximumCircular));
+        assert!("gap:x".parse::<Objective>().is_err());
+        assert!("max:2".parse::<Objective>().is_err());
+    }
+
+    #[test]
+    fn objective_examples() {
+        let digits = |line: &str, batteries: usize, objective: Objective| {
+            select_line(line, batteries, objective, 10).ok().map(|selection| selection.joltage().to_string())
+        };
+        assert_eq!(digits("3020410", 3, Objective::Minimum), Some("200".to_string()));
+        assert_eq!(digits("0001", 2, Objective::Minimum), None);
+        assert_eq!(digits("17113779", 3, Objective::MaximumWithGap(1)), Some("779".to_string()));
+        assert_eq!(digits("17113779", 3, Objective::MaximumWithGap(2)), Some("739".to_string()));
+        assert_eq!(digits("17113779", 3, Objective::MaximumWithGap(3)), None);
+        assert_eq!(digits("17113779", 2, Objective::MaximumWithGap(6)), Some("19".to_string()));
+        assert_eq!(digits("12399", 3, Objective::Maximum), Some("399".to_string()));
+        assert_eq!(digits("12399", 3, Objective::MaximumCircular), Some("993".to_string()));
+
+        let selection = select_line("12399", 3, Objective::MaximumCircular, 10).unwrap();
+        assert_eq!(selection.indices, vec![3, 4, 2]);
+        assert_eq!(selection.highlight("12399", false), "12[3][9][9]");
+    }
+
+    #[test]
+    fn objectives_match_brute_force() {
+        let objectives = [
+            Objective::Maximum,
+            Objective::Minimum,
+            Objective::MaximumWithGap(0),
+            Objective::MaximumWithGap(1),
+            Objective::MaximumWithGap(2),
+            Objective::MaximumCircular,
+        ];
+        for seed in 0..40 {
+            // Mix in zeros so the leading zero rule matters.
+            let bank: Vec<i64> = random_bank(10, seed).chars().map(|c| c.to_digit(10).unwrap() as i64 % 5).collect();
+            for batteries in 0..=bank.len() {
+                for objective in objectives {
+                    let selected = select_with(&bank, batteries, objective)
+                        .map(|indices| indices.iter().map(|&i| bank[i]).collect::<Vec<i64>>());
+                    assert_eq!(selected, brute_force(&bank, batteries, objective), "{:?} {} {:?}", bank, batteries, objective);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn circular_matches_every_start() {
+        // Reading the largest subsequence from every occurrence of the largest digit, on banks with
+        // few distinct digits so that many starts tie.
+        let every_start = |bank: &[i64], batteries: usize| {
+            let largest = *bank.iter().max().unwrap();
+            (0..bank.len())
+                .filter(|&start| bank[start] == largest)
+                .map(|start| {
+                    let rotated: Vec<i64> = bank[start..].iter().chain(&bank[..start]).copied().collect();
+                    select_batteries(&rotated, batteries).iter().map(|&i| rotated[i]).collect::<Vec<i64>>()
+                })
+                .max()
+                .unwrap()
+        };
+        for seed in 0..100 {
+            let bank: Vec<i64> = random_bank(60, seed).chars().map(|c| c.to_digit(10).unwrap() as i64 % 3).collect();
+            for batteries in 1..=bank.len() {
+                let selected: Vec<i64> = select_circular(&bank, batteries).iter().map(|&i| bank[i]).collect();
+                assert_eq!(selected, every_start(&bank, batteries), "{:?} {}", bank, batteries);
+            }
+        }
+
+        // Periodic banks tie on every start, which has to stay linear rather than comparing them all.
+        let bank: Vec<i64> = [9, 1].repeat(50_000);
+        let selected = select_circular(&bank, 75_000);
+        assert_eq!(selected.len(), 75_000);
+        assert_eq!(selected.iter().filter(|&&i| bank[i] == 9).count(), 50_000);
+        assert_eq!(select_circular(&[9; 100_000], 50_000).len(), 50_000);
+    }
+
+    #[test]
+    fn matches_rescan() {
+        let file = File::open("inputs/input.txt").expect("Could not open file");
+        for line in BufReader::new(file).lines() {
+            let line = line.unwrap();
+            for batteries in [1, 2, 5, 12, 18] {
+                assert_eq!(parse_line(&line, batteries), parse_line_rescan(&line, batteries as i32) as u64, "{}", line);
+            }
+        }
+        for seed in 0..200 {
+            let bank = random_bank(30, seed);
+            for batteries in 1..=18 {
+                assert_eq!(parse_line(&bank, batteries), parse_line_rescan(&bank, batteries as i32) as u64, "{}", bank);
+            }
+        }
+    }
+
+    // Times both selections on a bank of a million digits, which the rescan takes a while over
+    // outside release builds. Run with `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_million_digit_bank() {
+        let bank = random_bank(1_000_000, 2025);
+        for batteries in [2, 12, 18] {
+            let started = Instant::now();
+            let stack = parse_line(&bank, batteries);
+            let stack_time = started.elapsed();
+            let started = Instant::now();
+            let rescan = parse_line_rescan(&bank, batteries as i32);
+            let rescan_time = started.elapsed();
+            assert_eq!(stack, rescan as u64, "{} batteries", batteries);
+            println!("{} batteries: monotonic stack {:?}, rescan {:?}", batteries, stack_time, rescan_time);
+        }
+    }
 }
@@ -1,95 +1,766 @@
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, IsTerminal};
+use std::str::FromStr;
 
+// Which cells around a roll count as its neighbours.
+#[derive(Debug, Clone, PartialEq)]
+enum Neighborhood {
+    // The four cells sharing an edge.
+    VonNeumann,
+    // The eight cells sharing an edge or a corner, as in the puzzle.
+    Moore,
+    // Every other cell in the square of this radius around the roll.
+    MooreRadius(usize),
+    // Any set of (row, column) offsets, which need not be symmetric.
+    Custom(Vec<(isize, isize)>),
+}
 
-fn read_grid(filename: &str) -> Vec<Vec<i32>> {
-    let file = File::open(filename).expect("Could not open file");
-    let reader = BufReader::new(file);
+impl Neighborhood {
+    fn offsets(&self) -> Vec<(isize, isize)> {
+        let square = |radius: isize| {
+            (-radius..=radius)
+                .flat_map(move |dr| (-radius..=radius).map(move |dc| (dr, dc)))
+                .filter(|&offset| offset != (0, 0))
+                .collect()
+        };
+        match self {
+            Neighborhood::VonNeumann => vec![(-1, 0), (0, -1), (0, 1), (1, 0)],
+            Neighborhood::Moore => square(1),
+            Neighborhood::MooreRadius(radius) => square(*radius as isize),
+            Neighborhood::Custom(offsets) => offsets.clone(),
+        }
+    }
+}
 
-    let mut grid: Vec<Vec<i32>> = vec![];
+// Parses "von-neumann", "moore", "moore:<r>" or "custom:<dr>,<dc>;<dr>,<dc>;...".
+impl FromStr for Neighborhood {
+    type Err = String;
 
-    for line in reader.lines() {
-        let line = line.expect("Could not read line");
+    fn from_str(neighborhood: &str) -> Result<Self, Self::Err> {
+        match neighborhood.split_once(':') {
+            None if neighborhood == "von-neumann" => Ok(Neighborhood::VonNeumann),
+            None if neighborhood == "moore" => Ok(Neighborhood::Moore),
+            Some(("moore", radius)) => radius
+                .parse()
+                .map(Neighborhood::MooreRadius)
+                .map_err(|_| format!("Invalid radius ({})", radius)),
+            Some(("custom", offsets)) => {
+                let mut out: Vec<(isize, isize)> = vec![];
+                for offset in offsets.split(';') {
+                    let parsed = offset
+                        .split_once(',')
+                        .and_then(|(dr, dc)| Some((dr.trim().parse().ok()?, dc.trim().parse().ok()?)));
+                    match parsed {
+                        Some((0, 0)) => return Err("A roll can't be its own neighbour".to_string()),
+                        Some(offset) if !out.contains(&offset) => out.push(offset),
+                        Some(_) => {}
+                        None => return Err(format!("Invalid offset ({})", offset)),
+                    }
+                }
+                Ok(Neighborhood::Custom(out))
+            }
+            _ => Err(format!("Unknown neighbourhood ({})", neighborhood)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+}
 
-        let row: Vec<i32> = line.chars().map(|c| if c == '@' { 10 } else { 0 } as i32).collect();
-        grid.push(row);
+// When a roll can be reached by a forklift: its neighbour count compared against a threshold.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Accessibility {
+    comparison: Comparison,
+    threshold: u32,
+}
+
+impl Accessibility {
+    fn accepts(&self, neighbors: u32) -> bool {
+        match self.comparison {
+            Comparison::Less => neighbors < self.threshold,
+            Comparison::LessOrEqual => neighbors <= self.threshold,
+            Comparison::Equal => neighbors == self.threshold,
+            Comparison::GreaterOrEqual => neighbors >= self.threshold,
+            Comparison::Greater => neighbors > self.threshold,
+        }
     }
-    grid
 }
 
-fn process_neighbors(grid: &mut Vec<Vec<i32>>) {    
-    for i in 0..grid.len() {
-        for j in 0..grid[i].len() {
-            if grid[i][j] == 0 {
-                continue;
+// Parses a comparison followed by the threshold, such as "<4" or ">=2".
+impl FromStr for Accessibility {
+    type Err = String;
+
+    fn from_str(accessibility: &str) -> Result<Self, Self::Err> {
+        let comparisons = [
+            ("<=", Comparison::LessOrEqual),
+            (">=", Comparison::GreaterOrEqual),
+            ("<", Comparison::Less),
+            (">", Comparison::Greater),
+            ("=", Comparison::Equal),
+        ];
+        for (prefix, comparison) in comparisons {
+            if let Some(threshold) = accessibility.strip_prefix(prefix) {
+                let threshold = threshold.parse().map_err(|_| format!("Invalid threshold ({})", threshold))?;
+                return Ok(Accessibility { comparison, threshold });
             }
+        }
+        Err(format!("Unknown accessibility rule ({})", accessibility))
+    }
+}
+
+// What lies beyond the edges of the grid.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Boundary {
+    // Nothing, as in the puzzle.
+    Empty,
+    // The grid wraps around, so the cells past an edge are those along the opposite edge.
+    Torus,
+    // Rolls that never go away.
+    Rolls,
+}
+
+// Parses "empty", "torus" or "rolls".
+impl FromStr for Boundary {
+    type Err = String;
+
+    fn from_str(boundary: &str) -> Result<Self, Self::Err> {
+        match boundary {
+            "empty" => Ok(Boundary::Empty),
+            "torus" => Ok(Boundary::Torus),
+            "rolls" => Ok(Boundary::Rolls),
+            _ => Err(format!("Unknown boundary ({})", boundary)),
+        }
+    }
+}
+
+// The neighbourhood, accessibility rule and boundary the grid is peeled with.
+#[derive(Debug, Clone, PartialEq)]
+struct Rule {
+    neighborhood: Neighborhood,
+    accessibility: Accessibility,
+    boundary: Boundary,
+}
 
-            if i != 0 {
-                grid[i][j] += grid[i - 1][j] / 10;
+// The puzzle's rule: a roll is accessible when fewer than four of its eight neighbours are rolls,
+// and there is nothing past the edges.
+impl Default for Rule {
+    fn default() -> Self {
+        Rule {
+            neighborhood: Neighborhood::Moore,
+            accessibility: Accessibility { comparison: Comparison::Less, threshold: 4 },
+            boundary: Boundary::Empty,
+        }
+    }
+}
+
+// The positions of the cells at the given offsets from (row, col), or None for the offsets that
+// fall outside the grid. On a torus every offset wraps back into the grid, and on a small one
+// several offsets may land on the same cell, or on (row, col) itself.
+fn offset_cells(
+    width: usize,
+    height: usize,
+    row: usize,
+    col: usize,
+    offsets: &[(isize, isize)],
+    boundary: Boundary,
+) -> impl Iterator<Item = Option<usize>> + '_ {
+    offsets.iter().map(move |&(dr, dc)| {
+        let (r, c) = if boundary == Boundary::Torus {
+            (
+                (row as isize + dr).rem_euclid(height as isize) as usize,
+                (col as isize + dc).rem_euclid(width as isize) as usize,
+            )
+        } else {
+            (
+                row.checked_add_signed(dr).filter(|&r| r < height)?,
+                col.checked_add_signed(dc).filter(|&c| c < width)?,
+            )
+        };
+        Some(r * width + c)
+    })
+}
+
+// The paper rolls on the floor. Whether a cell holds a roll and how many of its neighbours hold
+// one are kept in separate, row-major vectors.
+#[derive(Debug, Clone, PartialEq)]
+struct Grid {
+    width: usize,
+    height: usize,
+    rolls: Vec<bool>,
+    neighbors: Vec<u32>,
+    rule: Rule,
+    // The neighbourhood's offsets, and the same offsets negated: the cells whose count includes a
+    // given cell, which differ from its neighbours when the neighbourhood isn't symmetric.
+    offsets: Vec<(isize, isize)>,
+    counted_by: Vec<(isize, isize)>,
+}
+
+impl Grid {
+    fn from_lines<I, S>(lines: I, rule: Rule) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut rolls: Vec<bool> = vec![];
+        let mut width = 0;
+        let mut height = 0;
+        for line in lines {
+            let line = line.as_ref();
+            if height == 0 {
+                width = line.chars().count();
+            } else if line.chars().count() != width {
+                panic!("Line {} ({}) is not {} cells wide", height + 1, line, width);
             }
-            if j != 0 {
-                grid[i][j] += grid[i][j-1] / 10;
+            rolls.extend(line.chars().map(|c| c == '@'));
+            height += 1;
+        }
+
+        let offsets = rule.neighborhood.offsets();
+        let counted_by = offsets.iter().map(|&(dr, dc)| (-dr, -dc)).collect();
+        let mut grid = Grid { width, height, rolls, neighbors: vec![0; width * height], rule, offsets, counted_by };
+        grid.count_neighbors();
+        grid
+    }
+
+    fn read(filename: &str, rule: Rule) -> Self {
+        let file = File::open(filename).expect("Could not open file");
+        let reader = BufReader::new(file);
+        Grid::from_lines(reader.lines().map(|line| line.expect("Could not read line")), rule)
+    }
+
+    fn count_neighbors(&mut self) {
+        let threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+        self.neighbors = self.counted_neighbors(threads);
+    }
+
+    fn count_at(&self, row: usize, col: usize) -> u32 {
+        offset_cells(self.width, self.height, row, col, &self.offsets, self.rule.boundary)
+            .filter(|cell| cell.map_or(self.rule.boundary == Boundary::Rolls, |i| self.rolls[i]))
+            .count() as u32
+    }
+
+    // Counts the neighbours of every cell into a new buffer, reading only the rolls, so the rows can
+    // be split into bands that are counted on separate threads. Every count is written by exactly
+    // one thread and depends only on the rolls, so the result is the same for any number of
+    // threads. Bands are kept to a few thousand cells, since smaller ones aren't worth a thread.
+    fn counted_neighbors(&self, threads: usize) -> Vec<u32> {
+        const MIN_BAND_CELLS: usize = 4096;
+        let mut counts = vec![0; self.width * self.height];
+        if counts.is_empty() {
+            return counts;
+        }
+        let rows_per_band = self.height.div_ceil(threads.max(1)).max(MIN_BAND_CELLS.div_ceil(self.width));
+        let count_band = |band: usize, chunk: &mut [u32]| {
+            let start = band * rows_per_band * self.width;
+            for (k, count) in chunk.iter_mut().enumerate() {
+                *count = self.count_at((start + k) / self.width, (start + k) % self.width);
             }
-            if i != 0 && j != 0 {
-                grid[i][j] += grid[i - 1][j - 1] / 10;
+        };
+        if rows_per_band >= self.height {
+            count_band(0, &mut counts);
+            return counts;
+        }
+        std::thread::scope(|scope| {
+            for (band, chunk) in counts.chunks_mut(rows_per_band * self.width).enumerate() {
+                scope.spawn(move || count_band(band, chunk));
             }
-            if i != 0 && j + 1 < grid[i].len() {
-                grid[i][j] += grid[i - 1][j + 1] / 10;
+        });
+        counts
+    }
+
+    fn is_accessible(&self, i: usize) -> bool {
+        self.rolls[i] && self.rule.accessibility.accepts(self.neighbors[i])
+    }
+
+    fn count_accessible(&self) -> usize {
+        (0..self.rolls.len()).filter(|&i| self.is_accessible(i)).count()
+    }
+
+    // Removes every roll that is accessible right now and recounts the neighbours, rescanning every
+    // cell on every round. The tests check peel's waves against it.
+    #[cfg(test)]
+    fn remove_accessible(&mut self) -> usize {
+        let accessible: Vec<usize> = (0..self.rolls.len()).filter(|&i| self.is_accessible(i)).collect();
+        for &i in &accessible {
+            self.rolls[i] = false;
+        }
+        self.count_neighbors();
+        accessible.len()
+    }
+
+    // The original peeling: remove_accessible until nothing is removed.
+    #[cfg(test)]
+    fn remove_all_rescanning(&mut self) -> usize {
+        let mut total_removed = 0;
+        loop {
+            let removed = self.remove_accessible();
+            if removed == 0 {
+                break;
             }
-            if j != 0 && i + 1 < grid.len() {
-                grid[i][j] += grid[i + 1][j - 1] / 10;
+            total_removed += removed;
+        }
+        total_removed
+    }
+
+    // Keeps removing accessible rolls until none are left and records when each one went.
+    // Rolls are removed in waves, each wave being every roll that is accessible once the previous
+    // wave is gone. A roll's accessibility can only change when one of its neighbours is removed,
+    // so after the first wave only the rolls whose count included a removed roll are looked at
+    // again, and their counts are decremented rather than recounted. Each roll is removed once and
+    // touches one cell per neighbour offset, so this is linear in the number of rolls.
+    fn peel(&mut self) -> RemovalHistory {
+        let mut history = RemovalHistory {
+            width: self.width,
+            cells: self.rolls.iter().map(|&roll| if roll { Removal::Never } else { Removal::Empty }).collect(),
+            removed_per_round: vec![],
+        };
+        let mut wave: Vec<usize> = (0..self.rolls.len()).filter(|&i| self.is_accessible(i)).collect();
+        let mut queued = vec![false; self.rolls.len()];
+        while !wave.is_empty() {
+            history.removed_per_round.push(wave.len());
+            let round = history.removed_per_round.len() as u32;
+            for &i in &wave {
+                self.rolls[i] = false;
+                history.cells[i] = Removal::Round(round);
             }
-            if j + 1 < grid[i].len() {
-                grid[i][j] += grid[i][j + 1] / 10;
+
+            let mut candidates: Vec<usize> = vec![];
+            for &i in &wave {
+                let (row, col) = (i / self.width, i % self.width);
+                let boundary = self.rule.boundary;
+                for neighbor in offset_cells(self.width, self.height, row, col, &self.counted_by, boundary).flatten() {
+                    self.neighbors[neighbor] -= 1;
+                    if self.rolls[neighbor] && !queued[neighbor] {
+                        queued[neighbor] = true;
+                        candidates.push(neighbor);
+                    }
+                }
             }
-            if i + 1 < grid.len() {
-                grid[i][j] += grid[i + 1][j] / 10;  
+            for &i in &candidates {
+                queued[i] = false;
             }
-            if i + 1 < grid.len() && j + 1 < grid[i].len() {
-                grid[i][j] += grid[i + 1][j + 1] / 10; 
+            wave = candidates.into_iter().filter(|&i| self.is_accessible(i)).collect();
+        }
+        history
+    }
+}
+
+// What happened to a cell while the grid was peeled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Removal {
+    // There was no roll there to begin with.
+    Empty,
+    // The roll was removed in this round, counting from 1, so round 1 holds part 1's rolls.
+    Round(u32),
+    // The roll was never accessible and is still there.
+    Never,
+}
+
+// The rounds in which the rolls of a grid were removed, cell by cell and in total.
+#[derive(Debug, Clone, PartialEq)]
+struct RemovalHistory {
+    width: usize,
+    cells: Vec<Removal>,
+    removed_per_round: Vec<usize>,
+}
+
+impl RemovalHistory {
+    fn rounds(&self) -> usize {
+        self.removed_per_round.len()
+    }
+
+    fn total_removed(&self) -> usize {
+        self.removed_per_round.iter().sum()
+    }
+
+    // The round map as text: "." for an empty cell, "@" for a roll that was never removed, and the
+    // round as a base 36 digit ("1" to "z") otherwise, with "+" for round 36 onwards. With color,
+    // removed rolls are also shaded from red for the first round to blue for the last.
+    fn render(&self, color: bool) -> String {
+        let last = self.rounds().max(2) as f64 - 1.0;
+        let mut out = String::new();
+        for row in self.cells.chunks(self.width.max(1)) {
+            for cell in row {
+                match *cell {
+                    Removal::Empty => out.push('.'),
+                    Removal::Never if color => out.push_str("\x1b[1m@\x1b[0m"),
+                    Removal::Never => out.push('@'),
+                    Removal::Round(round) => {
+                        let c = char::from_digit(round, 36).unwrap_or('+');
+                        if color {
+                            let late = (round - 1) as f64 / last;
+                            let (red, blue) = ((255.0 * (1.0 - late)) as u8, (255.0 * late) as u8);
+                            out.push_str(&format!("\x1b[38;2;255;255;255;48;2;{};64;{}m{}\x1b[0m", red, blue, c));
+                        } else {
+                            out.push(c);
+                        }
+                    }
+                }
             }
+            out.push('\n');
         }
+        out
     }
 }
 
-fn count_moveable(grid: &Vec<Vec<i32>>) -> i32 {
-    let mut count = 0;
-    for i in 0..grid.len() {
-        for j in 0..grid[i].len() {
-            if grid[i][j] >= 10 && grid[i][j] < 14 {
-                count += 1;
+// The rolls packed one bit per cell, 64 cells to a word with each row padded to whole words, for
+// maps too large to keep a byte and a count per cell. Only the Moore neighbourhood is supported:
+// the eight neighbour bits of a whole word of cells are lined up with shifts and added together
+// as bit-sliced 4-bit counters, so a word of counts costs a few dozen word operations.
+#[derive(Debug, Clone, PartialEq)]
+struct BitGrid {
+    width: usize,
+    height: usize,
+    words_per_row: usize,
+    bits: Vec<u64>,
+    // The neighbour counts the accessibility rule accepts, bit `count` set for each.
+    accepted: u16,
+    boundary: Boundary,
+    // The row past the top and bottom edges when it doesn't depend on the grid: no cells, or every
+    // cell. A torus reads the opposite edge's row instead.
+    outside: Vec<u64>,
+}
+
+impl BitGrid {
+    fn from_lines<I, S>(lines: I, rule: &Rule) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        if rule.neighborhood != Neighborhood::Moore {
+            panic!("The packed grid only counts Moore neighbourhoods");
+        }
+        let mut bits: Vec<u64> = vec![];
+        let mut width = 0;
+        let mut words_per_row = 0;
+        let mut height = 0;
+        for line in lines {
+            let line = line.as_ref();
+            if height == 0 {
+                width = line.chars().count();
+                words_per_row = width.div_ceil(64);
+            } else if line.chars().count() != width {
+                panic!("Line {} ({}) is not {} cells wide", height + 1, line, width);
+            }
+            let start = bits.len();
+            bits.resize(start + words_per_row, 0);
+            for (col, c) in line.chars().enumerate() {
+                if c == '@' {
+                    bits[start + col / 64] |= 1 << (col % 64);
+                }
             }
+            height += 1;
+        }
+        // Lines with no cells on them make a grid with nothing to count, whatever its height.
+        if width == 0 {
+            height = 0;
+        }
+        let accepted = (0..=8)
+            .filter(|&count| rule.accessibility.accepts(count))
+            .fold(0, |mask, count| mask | 1 << count);
+        let outside = (0..words_per_row)
+            .map(|w| match rule.boundary {
+                Boundary::Rolls if (w + 1) * 64 <= width => !0,
+                Boundary::Rolls => (1 << (width % 64)) - 1,
+                _ => 0,
+            })
+            .collect();
+        BitGrid { width, height, words_per_row, bits, accepted, boundary: rule.boundary, outside }
+    }
+
+    fn read(filename: &str, rule: &Rule) -> Self {
+        let file = File::open(filename).expect("Could not open file");
+        let reader = BufReader::new(file);
+        BitGrid::from_lines(reader.lines().map(|line| line.expect("Could not read line")), rule)
+    }
+
+    fn row(&self, row: usize) -> &[u64] {
+        &self.bits[row * self.words_per_row..(row + 1) * self.words_per_row]
+    }
+
+    // The row of cells past the top or bottom edge: none, every cell, or the opposite edge's row.
+    fn outside_row(&self, wrapped: usize) -> &[u64] {
+        match self.boundary {
+            Boundary::Torus => self.row(wrapped),
+            Boundary::Empty | Boundary::Rolls => &self.outside,
+        }
+    }
+
+    // Whether the cell past the left or right end of a line is a roll, given the cell at the other end.
+    fn outside_cell(&self, other_end: u64) -> u64 {
+        match self.boundary {
+            Boundary::Empty => 0,
+            Boundary::Rolls => 1,
+            Boundary::Torus => other_end,
+        }
+    }
+
+    // Writes the accessible rolls of a row into `out`. Bit j of a word is column 64 * w + j, so the
+    // left neighbours of a word are the row shifted up by one bit with the top bit of the previous
+    // word carried in, and the right neighbours the other way round. Padding bits are always clear,
+    // so nothing leaks in from past the right edge; the boundary's cells are put in explicitly.
+    fn accessible_row(&self, row: usize, out: &mut [u64]) {
+        let above = if row > 0 { self.row(row - 1) } else { self.outside_row(self.height - 1) };
+        let below = if row + 1 < self.height { self.row(row + 1) } else { self.outside_row(0) };
+        let middle = self.row(row);
+        let (last_word, last_bit) = ((self.width - 1) / 64, (self.width - 1) % 64);
+
+        for w in 0..self.words_per_row {
+            let mut planes = [0_u64; 4];
+            for (line, center) in [(above, true), (middle, false), (below, true)] {
+                let previous = if w > 0 { line[w - 1] >> 63 } else { self.outside_cell(line[last_word] >> last_bit & 1) };
+                let next = line.get(w + 1).copied().unwrap_or(0);
+                let left = (line[w] << 1) | previous;
+                let mut right = (line[w] >> 1) | (next << 63);
+                if w == last_word {
+                    right |= self.outside_cell(line[0] & 1) << last_bit;
+                }
+                let neighbors = if center { [left, line[w], right] } else { [left, right, 0] };
+                for mut carry in neighbors {
+                    for plane in planes.iter_mut() {
+                        let sum = *plane ^ carry;
+                        carry &= *plane;
+                        *plane = sum;
+                    }
+                }
+            }
+            let accessible = (0..=8).filter(|count| self.accepted >> count & 1 == 1).fold(0, |mask, count| {
+                let matches = (0..4).fold(!0_u64, |m, bit| {
+                    m & if count >> bit & 1 == 1 { planes[bit] } else { !planes[bit] }
+                });
+                mask | matches
+            });
+            out[w] = middle[w] & accessible;
+        }
+    }
+
+    fn count_accessible(&self) -> usize {
+        let mut out = vec![0; self.words_per_row];
+        (0..self.height)
+            .map(|row| {
+                self.accessible_row(row, &mut out);
+                out.iter().map(|word| word.count_ones() as usize).sum::<usize>()
+            })
+            .sum()
+    }
+
+    // Peels the grid round by round and returns how many rolls each round removed. A round's
+    // removals in a row can only be applied once the row below has been looked at, so they are held
+    // back by one row, and the first row's are held back to the end in case the grid wraps around.
+    // Only rows next to a row that changed in the previous round are looked at again. The held
+    // masks are swapped in and out of a few buffers that are reused across rows and rounds.
+    fn peel(&mut self) -> Vec<usize> {
+        let mut removed_per_round: Vec<usize> = vec![];
+        let mut changed = vec![true; self.height];
+        let mut changed_now = vec![false; self.height];
+        let mut current = vec![0; self.words_per_row];
+        let mut pending_mask = vec![0; self.words_per_row];
+        let mut first_mask = vec![0; self.words_per_row];
+        loop {
+            let mut removed = 0;
+            let mut pending: Option<usize> = None;
+            let mut first = false;
+            changed_now.fill(false);
+            for (row, row_changed) in changed_now.iter_mut().enumerate() {
+                let mut near = [row.wrapping_sub(1), row, row + 1];
+                if self.boundary == Boundary::Torus {
+                    for r in near.iter_mut() {
+                        *r = r.wrapping_add(self.height) % self.height;
+                    }
+                }
+                if near.iter().any(|&r| changed.get(r).copied().unwrap_or(false)) {
+                    self.accessible_row(row, &mut current);
+                    let count: usize = current.iter().map(|word| word.count_ones() as usize).sum();
+                    if count > 0 {
+                        removed += count;
+                        *row_changed = true;
+                    }
+                }
+                if let Some(pending_row) = pending.take() {
+                    self.clear(pending_row, &pending_mask);
+                }
+                if *row_changed {
+                    if row == 0 {
+                        std::mem::swap(&mut first_mask, &mut current);
+                        first = true;
+                    } else {
+                        std::mem::swap(&mut pending_mask, &mut current);
+                        pending = Some(row);
+                    }
+                }
+            }
+            if let Some(pending_row) = pending {
+                self.clear(pending_row, &pending_mask);
+            }
+            if first {
+                self.clear(0, &first_mask);
+            }
+            if removed == 0 {
+                return removed_per_round;
+            }
+            removed_per_round.push(removed);
+            std::mem::swap(&mut changed, &mut changed_now);
+        }
+    }
+
+    fn clear(&mut self, row: usize, mask: &[u64]) {
+        let start = row * self.words_per_row;
+        for (word, &m) in self.bits[start..start + self.words_per_row].iter_mut().zip(mask) {
+            *word &= !m;
         }
     }
-    count
 }
 
-fn remove_rolls(grid: &mut Vec<Vec<i32>>) -> i32 {
-    let mut removed = 0;
-    for i in 0..grid.len() {
-        for j in 0..grid[i].len() {
-            if grid[i][j] >= 10 && grid[i][j] < 14 {
-                grid[i][j] = 0;
+// The original grid, where a roll is stored as 10 and its neighbour count is added to it.
+#[cfg(test)]
+mod encoded {
+    use std::fs::File;
+    use std::io::{BufRead, BufReader};
+
+    pub fn read_grid(filename: &str) -> Vec<Vec<i32>> {
+        let file = File::open(filename).expect("Could not open file");
+        let reader = BufReader::new(file);
+
+        let mut grid: Vec<Vec<i32>> = vec![];
+
+        for line in reader.lines() {
+            let line = line.expect("Could not read line");
+
+            let row: Vec<i32> = line.chars().map(|c| if c == '@' { 10 } else { 0 }).collect();
+            grid.push(row);
+        }
+        grid
+    }
+
+    pub fn process_neighbors(grid: &mut [Vec<i32>]) {
+        for i in 0..grid.len() {
+            for j in 0..grid[i].len() {
+                if grid[i][j] == 0 {
+                    continue;
+                }
+
+                if i != 0 {
+                    grid[i][j] += grid[i - 1][j] / 10;
+                }
+                if j != 0 {
+                    grid[i][j] += grid[i][j-1] / 10;
+                }
+                if i != 0 && j != 0 {
+                    grid[i][j] += grid[i - 1][j - 1] / 10;
+                }
+                if i != 0 && j + 1 < grid[i].len() {
+                    grid[i][j] += grid[i - 1][j + 1] / 10;
+                }
+                if j != 0 && i + 1 < grid.len() {
+                    grid[i][j] += grid[i + 1][j - 1] / 10;
+                }
+                if j + 1 < grid[i].len() {
+                    grid[i][j] += grid[i][j + 1] / 10;
+                }
+                if i + 1 < grid.len() {
+                    grid[i][j] += grid[i + 1][j] / 10;
+                }
+                if i + 1 < grid.len() && j + 1 < grid[i].len() {
+                    grid[i][j] += grid[i + 1][j + 1] / 10;
+                }
+            }
+        }
+    }
+
+    pub fn count_moveable(grid: &[Vec<i32>]) -> i32 {
+        grid.iter().flatten().filter(|&&cell| (10..14).contains(&cell)).count() as i32
+    }
+
+    fn remove_rolls(grid: &mut [Vec<i32>]) -> i32 {
+        let mut removed = 0;
+        for cell in grid.iter_mut().flatten() {
+            if (10..14).contains(cell) {
+                *cell = 0;
                 removed += 1;
-            } else if grid[i][j] >= 10 {
-                grid[i][j] = 10;
+            } else if *cell >= 10 {
+                *cell = 10;
             }
         }
+        process_neighbors(grid);
+        removed
+    }
+
+    pub fn remove_all(grid: &mut [Vec<i32>]) -> i32 {
+        let mut total_removed = 0;
+        loop {
+            let removed = remove_rolls(grid);
+            if removed == 0 {
+                break;
+            }
+            total_removed += removed;
+        }
+        total_removed
     }
-    process_neighbors(grid);
-    removed
 }
 
-fn remove_all(grid: &mut Vec<Vec<i32>>) -> i32 {
-    let mut total_removed = 0;
-    loop {
-        let removed = remove_rolls(grid);
-        if removed == 0 {
-            break;
+fn main() {
+    // "--neighborhood <neighbourhood>" and "--accessible <rule>" peel the grid with another rule,
+    // such as "--neighborhood von-neumann --accessible <=1".
+    // "--rounds" prints the round in which every roll was removed, as a heat map on a terminal and
+    // as a text grid otherwise, followed by the number of rolls removed in each round.
+    // "--packed" works on a bit-packed grid, for very large maps, and "--input <file>" reads the
+    // map from another file than the puzzle input.
+    // "--boundary <empty|torus|rolls>" picks what lies past the edges of the map.
+    let mut args = std::env::args().skip(1);
+    let mut rule = Rule::default();
+    let mut rounds = false;
+    let mut packed = false;
+    let mut filename = "inputs/input.txt".to_string();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--rounds" => rounds = true,
+            "--packed" => packed = true,
+            "--input" => filename = args.next().expect("Expected a file after --input"),
+            "--boundary" => {
+                let value = args.next().expect("Expected a boundary after --boundary");
+                rule.boundary = value.parse().unwrap_or_else(|error| panic!("{}", error));
+            }
+            "--neighborhood" => {
+                let value = args.next().expect("Expected a neighbourhood after --neighborhood");
+                rule.neighborhood = value.parse().unwrap_or_else(|error| panic!("{}", error));
+            }
+            "--accessible" => {
+                let value = args.next().expect("Expected a rule after --accessible");
+                rule.accessibility = value.parse().unwrap_or_else(|error| panic!("{}", error));
+            }
+            _ => panic!("Unknown argument ({})", arg),
+        }
+    }
+
+    if packed {
+        if rounds {
+            panic!("The packed grid doesn't keep a round map");
         }
-        total_removed += removed;
+        let mut grid = BitGrid::read(&filename, &rule);
+        println!("Moveable papers, Part 1: {}", grid.count_accessible());
+        println!("Total removed papers, Part 2: {}", grid.peel().iter().sum::<usize>());
+        return;
     }
-    total_removed
+
+    let mut grid = Grid::read(&filename, rule);
+    let moveable_papers = grid.count_accessible();
+    let history = grid.peel();
+    if rounds {
+        print!("{}", history.render(std::io::stdout().is_terminal()));
+        for (round, removed) in history.removed_per_round.iter().enumerate() {
+            println!("Round {}: {} removed", round + 1, removed);
+        }
+        return;
+    }
+
+    println!("Moveable papers, Part 1: {}", moveable_papers);
+    println!("Total removed papers, Part 2: {}", history.total_removed());
 }
 
 #[cfg(test)]
@@ -98,25 +769,321 @@ mod tests {
 
     #[test]
     fn example() {
-        let mut grid = read_grid("inputs/example.txt");
-        process_neighbors(&mut grid);
-        assert_eq!(count_moveable(&grid), 13);
+        let grid = Grid::read("inputs/example.txt", Rule::default());
+        assert_eq!(grid.count_accessible(), 13);
     }
 
     #[test]
     fn example_part2() {
-        let mut grid = read_grid("inputs/example.txt");
-        process_neighbors(&mut grid);
-        let total_removed = remove_all(&mut grid);
+        let mut grid = Grid::read("inputs/example.txt", Rule::default());
+        let total_removed = grid.peel().total_removed();
         assert_eq!(total_removed, 43);
     }
-}
 
-fn main() {
-    let mut grid = read_grid("inputs/input.txt");
-    process_neighbors(&mut grid);
-    let moveable_papers = count_moveable(&grid);
-    println!("Moveable papers, Part 1: {}", moveable_papers);
-    let total_removed = remove_all(&mut grid);
-    println!("Total removed papers, Part 2: {}", total_removed);
+    #[test]
+    fn example_history() {
+        let mut grid = Grid::read("inputs/example.txt", Rule::default());
+        let history = grid.peel();
+        assert_eq!(history.removed_per_round, vec![13, 12, 7, 5, 2, 1, 1, 1, 1]);
+        assert_eq!(history.rounds(), 9);
+        assert_eq!(history.total_removed(), 43);
+        assert_eq!(history.cells[0], Removal::Empty);
+        assert_eq!(history.cells[2], Removal::Round(1));
+        assert_eq!(history.cells[44], Removal::Never);
+
+        let text = history.render(false);
+        assert_eq!(text.lines().count(), 10);
+        assert_eq!(text.matches('1').count(), 13);
+        assert_eq!(text.matches('@').count(), grid.rolls.iter().filter(|&&roll| roll).count());
+        // The rounds map back onto the grid the history came from.
+        let original = Grid::read("inputs/example.txt", Rule::default());
+        for (cell, &roll) in history.cells.iter().zip(&original.rolls) {
+            assert_eq!(*cell != Removal::Empty, roll);
+        }
+    }
+
+    #[test]
+    fn render_history() {
+        let rule = Rule { accessibility: "<2".parse().unwrap(), ..Rule::default() };
+        let mut grid = Grid::from_lines(["@@@.@@", "....@@"], rule);
+        let history = grid.peel();
+        assert_eq!(history.render(false), "121.@@\n....@@\n");
+        let heat_map = history.render(true);
+        assert!(heat_map.contains("\x1b[38;2;255;255;255;48;2;255;64;0m1\x1b[0m"));
+        assert!(heat_map.contains("\x1b[38;2;255;255;255;48;2;0;64;255m2\x1b[0m"));
+        assert!(heat_map.contains("\x1b[1m@\x1b[0m"));
+    }
+
+    #[test]
+    fn neighbor_counts() {
+        let grid = Grid::from_lines(["@@.", ".@@", "@.."], Rule::default());
+        assert_eq!(grid.rolls, vec![true, true, false, false, true, true, true, false, false]);
+        assert_eq!(grid.neighbors, vec![2, 3, 3, 4, 4, 2, 1, 3, 2]);
+        // Empty cells have neighbour counts too, but only rolls can be accessible.
+        assert!(!grid.is_accessible(2));
+        assert!(grid.is_accessible(1));
+        assert!(!grid.is_accessible(4));
+    }
+
+    // A reproducible grid where roughly `density` percent of the cells are rolls.
+    fn random_lines(width: usize, height: usize, density: u64, seed: u64) -> Vec<String> {
+        let mut state = seed;
+        (0..height)
+            .map(|_| {
+                (0..width)
+                    .map(|_| {
+                        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                        if (state >> 33) % 100 < density { '@' } else { '.' }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn random_grid(width: usize, height: usize, density: u64, seed: u64, rule: Rule) -> Grid {
+        Grid::from_lines(random_lines(width, height, density, seed), rule)
+    }
+
+    #[test]
+    fn waves_match_rescanning() {
+        let mut grids = vec![Grid::read("inputs/example.txt", Rule::default()), Grid::read("inputs/input.txt", Rule::default())];
+        grids.extend((0..20).map(|seed| random_grid(40, 30, 40 + 2 * seed, seed, Rule::default())));
+        for grid in grids {
+            let mut rescanned = grid.clone();
+            let mut peeled = grid;
+            let history = peeled.peel();
+            // Each wave is one full rescan's worth of accessible rolls.
+            for &removed in &history.removed_per_round {
+                assert_eq!(rescanned.remove_accessible(), removed);
+            }
+            assert_eq!(rescanned.remove_accessible(), 0);
+            // The decremented counts end up where a full recount would put them.
+            assert_eq!(peeled, rescanned);
+        }
+    }
+
+    #[test]
+    fn parse_rules() {
+        assert_eq!("von-neumann".parse(), Ok(Neighborhood::VonNeumann));
+        assert_eq!("moore".parse(), Ok(Neighborhood::Moore));
+        assert_eq!("moore:2".parse(), Ok(Neighborhood::MooreRadius(2)));
+        assert_eq!("custom:-1,0; 0,2;-1,0".parse(), Ok(Neighborhood::Custom(vec![(-1, 0), (0, 2)])));
+        assert!("custom:0,0".parse::<Neighborhood>().is_err());
+        assert!("custom:1".parse::<Neighborhood>().is_err());
+        assert!("hex".parse::<Neighborhood>().is_err());
+
+        assert_eq!("<4".parse(), Ok(Accessibility { comparison: Comparison::Less, threshold: 4 }));
+        assert_eq!("<=4".parse(), Ok(Accessibility { comparison: Comparison::LessOrEqual, threshold: 4 }));
+        assert_eq!(">=2".parse(), Ok(Accessibility { comparison: Comparison::GreaterOrEqual, threshold: 2 }));
+        assert_eq!("=0".parse(), Ok(Accessibility { comparison: Comparison::Equal, threshold: 0 }));
+        assert!("4".parse::<Accessibility>().is_err());
+        assert!("<x".parse::<Accessibility>().is_err());
+    }
+
+    #[test]
+    fn neighborhood_shapes() {
+        assert_eq!(Neighborhood::Moore.offsets().len(), 8);
+        assert_eq!(Neighborhood::MooreRadius(1).offsets(), Neighborhood::Moore.offsets());
+        assert_eq!(Neighborhood::MooreRadius(2).offsets().len(), 24);
+
+        let lines = ["@@.", ".@@", "@.."];
+        let rule = |neighborhood: Neighborhood| Rule { neighborhood, ..Rule::default() };
+        let grid = Grid::from_lines(lines, rule(Neighborhood::VonNeumann));
+        assert_eq!(grid.neighbors, vec![1, 2, 2, 3, 2, 1, 0, 2, 1]);
+        let grid = Grid::from_lines(lines, rule(Neighborhood::MooreRadius(2)));
+        assert_eq!(grid.neighbors, vec![4, 4, 5, 5, 4, 4, 4, 5, 5]);
+        // Every cell counts the cell to its right only.
+        let grid = Grid::from_lines(lines, rule(Neighborhood::Custom(vec![(0, 1)])));
+        assert_eq!(grid.neighbors, vec![1, 0, 0, 1, 1, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn variant_rules() {
+        let rule = |neighborhood: &str, accessibility: &str| Rule {
+            neighborhood: neighborhood.parse().unwrap(),
+            accessibility: accessibility.parse().unwrap(),
+            ..Rule::default()
+        };
+        // Isolated rolls only: the middle of a plus shape is never accessible.
+        let mut grid = Grid::from_lines([".@.", "@@@", ".@."], rule("von-neumann", "<=1"));
+        assert_eq!(grid.count_accessible(), 4);
+        assert_eq!(grid.peel().total_removed(), 5);
+        // With a rule that needs crowding, nothing here is ever accessible.
+        let mut grid = Grid::from_lines([".@.", "@@@", ".@."], rule("von-neumann", ">=5"));
+        assert_eq!(grid.peel().total_removed(), 0);
+        // A chain where each roll only counts the one to its right peels from the right end.
+        let mut grid = Grid::from_lines(["@@@@@"], rule("custom:0,1", "=0"));
+        assert_eq!(grid.count_accessible(), 1);
+        assert_eq!(grid.peel().total_removed(), 5);
+    }
+
+    #[test]
+    fn variant_waves_match_rescanning() {
+        let neighborhoods = ["von-neumann", "moore", "moore:2", "custom:0,1;2,-1;-1,-1"];
+        let accessibilities = ["<4", "<=2", "=3", ">=6", ">2"];
+        let boundaries = [Boundary::Empty, Boundary::Torus, Boundary::Rolls];
+        let mut seed = 0;
+        for neighborhood in neighborhoods {
+            for accessibility in accessibilities {
+                for boundary in boundaries {
+                    let rule = Rule {
+                        neighborhood: neighborhood.parse().unwrap(),
+                        accessibility: accessibility.parse().unwrap(),
+                        boundary,
+                    };
+                    for density in [30, 60, 90] {
+                        seed += 1;
+                        let grid = random_grid(25, 20, density, seed, rule.clone());
+                        let mut rescanned = grid.clone();
+                        let mut peeled = grid;
+                        assert_eq!(peeled.peel().total_removed(), rescanned.remove_all_rescanning(), "{:?}", rule);
+                        assert_eq!(peeled, rescanned, "{:?}", rule);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn parallel_counts_match_sequential() {
+        let rules = [
+            Rule::default(),
+            Rule { neighborhood: Neighborhood::MooreRadius(2), boundary: Boundary::Torus, ..Rule::default() },
+            Rule { neighborhood: Neighborhood::VonNeumann, boundary: Boundary::Rolls, ..Rule::default() },
+        ];
+        for rule in rules {
+            // Tall and narrow, so the rows are split into several bands.
+            let sequential = random_grid(30, 700, 65, 11, rule.clone());
+            let expected = sequential.counted_neighbors(1);
+            assert_eq!(sequential.neighbors, expected);
+            for threads in [2, 3, 8, 64] {
+                assert_eq!(sequential.counted_neighbors(threads), expected, "{} {:?}", threads, rule);
+                let mut parallel = sequential.clone();
+                parallel.neighbors = parallel.counted_neighbors(threads);
+                assert_eq!(parallel.count_accessible(), sequential.count_accessible());
+                assert_eq!(parallel.peel(), sequential.clone().peel());
+            }
+        }
+
+        let mut grids = vec![Grid::read("inputs/input.txt", Rule::default())];
+        grids.extend((0..10).map(|seed| random_grid(80 + 13 * seed as usize, 120, 40 + 5 * seed, seed, Rule::default())));
+        for grid in grids {
+            let expected = grid.counted_neighbors(1);
+            let peeled = grid.clone().peel();
+            for threads in [2, 4, 7] {
+                let mut parallel = grid.clone();
+                parallel.neighbors = grid.counted_neighbors(threads);
+                assert_eq!(parallel.neighbors, expected, "{} threads", threads);
+                assert_eq!(parallel.count_accessible(), grid.count_accessible(), "{} threads", threads);
+                assert_eq!(parallel.peel(), peeled, "{} threads", threads);
+            }
+        }
+    }
+
+    #[test]
+    fn boundaries() {
+        assert_eq!("empty".parse(), Ok(Boundary::Empty));
+        assert_eq!("torus".parse(), Ok(Boundary::Torus));
+        assert_eq!("rolls".parse(), Ok(Boundary::Rolls));
+        assert!("walls".parse::<Boundary>().is_err());
+
+        let rule = |boundary| Rule { boundary, ..Rule::default() };
+        let block = ["@@@", "@@@", "@@@"];
+        let mut grid = Grid::from_lines(block, rule(Boundary::Empty));
+        assert_eq!((grid.count_accessible(), grid.peel().total_removed()), (4, 9));
+        // On a torus every roll of a full grid has eight neighbours, and so does every roll with
+        // rolls all around.
+        let mut grid = Grid::from_lines(block, rule(Boundary::Torus));
+        assert_eq!(grid.neighbors, vec![8; 9]);
+        assert_eq!((grid.count_accessible(), grid.peel().total_removed()), (0, 0));
+        let mut grid = Grid::from_lines(block, rule(Boundary::Rolls));
+        assert_eq!((grid.count_accessible(), grid.peel().total_removed()), (0, 0));
+
+        // Opposite corners touch on a torus. Against rolls, a corner has five outside neighbours.
+        let corners = ["@...", "....", "....", "...@"];
+        let grid = Grid::from_lines(corners, rule(Boundary::Torus));
+        assert_eq!((grid.neighbors[0], grid.neighbors[15]), (1, 1));
+        let grid = Grid::from_lines(corners, rule(Boundary::Rolls));
+        assert_eq!((grid.neighbors[0], grid.neighbors[15], grid.neighbors[5]), (5, 5, 1));
+        assert_eq!(grid.count_accessible(), 0);
+
+        // A single roll on a 1 by 1 torus is its own neighbour eight times over.
+        let grid = Grid::from_lines(["@"], rule(Boundary::Torus));
+        assert_eq!(grid.neighbors, vec![8]);
+
+        for boundary in [Boundary::Empty, Boundary::Torus, Boundary::Rolls] {
+            let mut grid = Grid::read("inputs/example.txt", rule(boundary));
+            let mut packed = BitGrid::read("inputs/example.txt", &rule(boundary));
+            assert_eq!(grid.count_accessible(), packed.count_accessible(), "{:?}", boundary);
+            assert_eq!(grid.peel().removed_per_round, packed.peel(), "{:?}", boundary);
+        }
+    }
+
+    #[test]
+    fn packed_example() {
+        let mut grid = BitGrid::read("inputs/example.txt", &Rule::default());
+        assert_eq!(grid.count_accessible(), 13);
+        assert_eq!(grid.peel(), vec![13, 12, 7, 5, 2, 1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn packed_matches_grid() {
+        let accessibilities = ["<4", "<=2", "=3", ">=6", ">2", "<9"];
+        let boundaries = [Boundary::Empty, Boundary::Torus, Boundary::Rolls];
+        let mut seed = 0;
+        for (width, height) in [(1, 1), (2, 3), (5, 17), (63, 17), (64, 2), (65, 17), (130, 9)] {
+            for accessibility in accessibilities {
+                for boundary in boundaries {
+                    seed += 1;
+                    let rule = Rule { accessibility: accessibility.parse().unwrap(), boundary, ..Rule::default() };
+                    let lines = random_lines(width, height, 30 + seed % 60, seed);
+                    let mut packed = BitGrid::from_lines(&lines, &rule);
+                    let mut grid = Grid::from_lines(&lines, rule.clone());
+                    assert_eq!(packed.count_accessible(), grid.count_accessible(), "{} {:?}", width, rule);
+                    assert_eq!(packed.peel(), grid.peel().removed_per_round, "{} {:?}", width, rule);
+                    let remaining: usize = packed.bits.iter().map(|word| word.count_ones() as usize).sum();
+                    assert_eq!(remaining, grid.rolls.iter().filter(|&&roll| roll).count());
+                }
+            }
+        }
+
+        let mut packed = BitGrid::read("inputs/input.txt", &Rule::default());
+        let mut grid = Grid::read("inputs/input.txt", Rule::default());
+        assert_eq!(packed.count_accessible(), grid.count_accessible());
+        assert_eq!(packed.peel(), grid.peel().removed_per_round);
+
+        // Lines without cells leave nothing to count, on any boundary.
+        for boundary in boundaries {
+            let rule = Rule { boundary, ..Rule::default() };
+            let mut packed = BitGrid::from_lines(["", ""], &rule);
+            assert_eq!((packed.count_accessible(), packed.peel()), (0, vec![]));
+        }
+    }
+
+    // Tens of millions of cells, which take a while outside release builds. Run with
+    // `cargo test --release -- --ignored --nocapture` to see how much memory the packed grid uses.
+    #[test]
+    #[ignore]
+    fn packed_large_map() {
+        let (width, height) = (6000, 6000);
+        let lines = random_lines(width, height, 65, 2025);
+        let mut packed = BitGrid::from_lines(&lines, &Rule::default());
+        // One bit per cell, rounded up to whole words per row.
+        assert_eq!(packed.bits.len(), height * width.div_ceil(64));
+        println!("{} cells packed into {} KiB", width * height, packed.bits.len() * 8 / 1024);
+        let mut grid = Grid::from_lines(&lines, Rule::default());
+        assert_eq!(packed.count_accessible(), grid.count_accessible());
+        assert_eq!(packed.peel(), grid.peel().removed_per_round);
+    }
+
+    #[test]
+    fn matches_encoded_grid() {
+        for filename in ["inputs/example.txt", "inputs/input.txt"] {
+            let mut encoded_grid = encoded::read_grid(filename);
+            encoded::process_neighbors(&mut encoded_grid);
+            let mut grid = Grid::read(filename, Rule::default());
+            assert_eq!(grid.count_accessible(), encoded::count_moveable(&encoded_grid) as usize, "{}", filename);
+            assert_eq!(grid.peel().total_removed(), encoded::remove_all(&mut encoded_grid) as usize, "{}", filename);
+        }
+    }
 }
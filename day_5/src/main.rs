@@ -1,15 +1,40 @@
+use std::fmt;
+use std::str::FromStr;
 
-#[derive(PartialOrd, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 struct FreshRange {
     start: u64,
     end: u64,
 }
 
+#[derive(Debug, PartialEq)]
 struct FreshRanges {
     
     min: u64,
     max: u64,
     ranges: Vec<FreshRange>,
+    // Whether ranges that touch without overlapping, like 3-5 and 6-8, are merged as well.
+    merge_adjacent: bool,
+}
+
+// How the end of a range is read from the input: "3-5" holds 3, 4 and 5 when inclusive, and only
+// 3 and 4 when half-open. Ranges are always stored inclusive, so that one can end at u64::MAX.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Bounds {
+    Inclusive,
+    HalfOpen,
+}
+
+impl FromStr for Bounds {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "inclusive" => Ok(Bounds::Inclusive),
+            "half-open" => Ok(Bounds::HalfOpen),
+            _ => Err(format!("Unknown bounds ({})", s)),
+        }
+    }
 }
 
 impl FreshRange{
@@ -17,12 +42,15 @@ impl FreshRange{
         FreshRange { start, end }
     }
 
-    // Merges two ranges and modifies self with the merged range. If the ranges do not overlap, returns false.
-    fn merge(&mut self, other: &FreshRange) -> bool{
+    // Merges two ranges and modifies self with the merged range. Returns false if the ranges
+    // neither overlap nor, when adjacent is set, touch.
+    fn merge(&mut self, other: &FreshRange, adjacent: bool) -> bool{
+        let touches = |a: &FreshRange, b: &FreshRange| a.end.checked_add(1) == Some(b.start);
         if (self.start >= other.start && self.start <= other.end) ||
            (self.end >= other.start && self.end <= other.end) ||
            (other.start >=self.start && other.start <= self.end) ||
-           (other.end >= self.start && other.end <= self.end) { 
+           (other.end >= self.start && other.end <= self.end) ||
+           (adjacent && (touches(self, other) || touches(other, self))) {
             self.start = self.start.min(other.start);
             self.end = self.end.max(other.end);
             true
@@ -30,6 +58,11 @@ impl FreshRange{
             false
         }
     }
+
+    // The number of IDs in the range. A range can hold every u64, one more than a u64 can count.
+    fn size(&self) -> u128 {
+        (self.end - self.start) as u128 + 1
+    }
 }
 
 impl Ord for FreshRange {
@@ -38,25 +71,53 @@ impl Ord for FreshRange {
     }
 }
 
+impl fmt::Display for FreshRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
+impl PartialOrd for FreshRange {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 impl FreshRanges {
-    fn new() -> Self {
+    fn new(merge_adjacent: bool) -> Self {
         FreshRanges {
             min: u64::MAX,
             max: 0,
             ranges: Vec::new(),
+            merge_adjacent,
+        }
+    }
+
+    // Builds the merged set from a whole list of ranges at once. Once the ranges are sorted, each
+    // one either merges into the last merged range or starts a new one, so a single pass merges
+    // them all: O(n log n) for the sort, where calling add_range for each range is quadratic.
+    fn from_ranges<I: IntoIterator<Item = FreshRange>>(ranges: I, merge_adjacent: bool) -> Self {
+        let mut ranges: Vec<FreshRange> = ranges.into_iter().collect();
+        ranges.sort_unstable();
+        let mut fresh_ranges = FreshRanges::new(merge_adjacent);
+        for range in ranges {
+            fresh_ranges.push_sorted(range);
         }
+        fresh_ranges
     }
 
+    // Merges a single range into the set, for ranges added after it was built. Each call walks the
+    // ranges, so build a whole list with from_ranges instead.
     fn add_range(&mut self, new_range: FreshRange) {
         self.min = self.min.min(new_range.start);
         self.max = self.max.max(new_range.end);
 
         let mut merged = false;
         for index in 0..self.ranges.len() {
-            if self.ranges[index].merge(&new_range) {
+            if self.ranges[index].merge(&new_range, self.merge_adjacent) {
                 while index + 1 < self.ranges.len() {
                     let (left, right) = self.ranges.split_at_mut(index + 1);
-                    if left[index].merge(&right[0]) {
+                    if left[index].merge(&right[0], self.merge_adjacent) {
                         self.ranges.remove(index + 1);
                     } else {
                         break;
@@ -75,7 +136,137 @@ impl FreshRanges {
         self.ranges.sort();
     }
 
+    // Appends a range that starts no earlier than every range so far, merging it into the last one
+    // if they overlap, or touch when merge_adjacent is set.
+    fn push_sorted(&mut self, new_range: FreshRange) {
+        self.min = self.min.min(new_range.start);
+        self.max = self.max.max(new_range.end);
+        if let Some(last) = self.ranges.last_mut()
+            && last.merge(&new_range, self.merge_adjacent)
+        {
+            return;
+        }
+        self.ranges.push(new_range);
+    }
+
+    // The ranges are sorted and disjoint, so their ends are sorted too: the only range that can
+    // hold the value is the first one that doesn't end before it.
     fn check_fresh(&self, value: u64) -> bool {
+        if value < self.min || value > self.max {
+            return false;
+        }
+        let index = self.ranges.partition_point(|range| range.end < value);
+        self.ranges.get(index).is_some_and(|range| range.start <= value)
+    }
+
+    // Checks a batch of IDs at once and returns whether each one is fresh, in the order given. The
+    // IDs are sorted once and swept against the ranges, so the range pointer only moves forward. A
+    // batch too small to be worth walking every range is looked up one ID at a time instead.
+    fn check_fresh_all(&self, values: &[u64]) -> Vec<bool> {
+        if values.len() * (self.ranges.len().max(1).ilog2() as usize + 1) < self.ranges.len() {
+            return values.iter().map(|&value| self.check_fresh(value)).collect();
+        }
+        let mut order: Vec<usize> = (0..values.len()).collect();
+        order.sort_unstable_by_key(|&i| values[i]);
+
+        let mut fresh = vec![false; values.len()];
+        let mut ranges = self.ranges.iter().peekable();
+        for i in order {
+            while ranges.next_if(|range| range.end < values[i]).is_some() {}
+            fresh[i] = ranges.peek().is_some_and(|range| range.start <= values[i]);
+        }
+        fresh
+    }
+
+    // The set operations below take two normalized sets, sorted with no overlaps, and sweep
+    // through their ranges in order, so each is linear in the number of ranges. They return new
+    // normalized sets whose min and max are those of the ranges they ended up with, and which
+    // merge adjacent ranges if self does.
+
+    fn union(&self, other: &FreshRanges) -> FreshRanges {
+        let mut out = FreshRanges::new(self.merge_adjacent);
+        let (mut a, mut b) = (self.ranges.iter().peekable(), other.ranges.iter().peekable());
+        loop {
+            // Take whichever range comes first, so the ranges are pushed in order without a sort.
+            let next = match (a.peek(), b.peek()) {
+                (Some(x), Some(y)) if y < x => b.next(),
+                (Some(_), _) => a.next(),
+                (None, _) => b.next(),
+            };
+            let Some(range) = next else { break };
+            out.push_sorted(range.clone());
+        }
+        out
+    }
+
+    fn intersection(&self, other: &FreshRanges) -> FreshRanges {
+        let mut out = FreshRanges::new(self.merge_adjacent);
+        let (mut i, mut j) = (0, 0);
+        while let (Some(a), Some(b)) = (self.ranges.get(i), other.ranges.get(j)) {
+            let (start, end) = (a.start.max(b.start), a.end.min(b.end));
+            if start <= end {
+                out.push_sorted(FreshRange::new(start, end));
+            }
+            // The range that ends first can't reach anything further along the other set.
+            if a.end < b.end {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        out
+    }
+
+    // The IDs in self that are not in other.
+    fn difference(&self, other: &FreshRanges) -> FreshRanges {
+        let mut out = FreshRanges::new(self.merge_adjacent);
+        let mut j = 0;
+        for range in &self.ranges {
+            while other.ranges.get(j).is_some_and(|cut| cut.end < range.start) {
+                j += 1;
+            }
+            // What is left of the range lies from `next` on, until a cut ends at u64::MAX.
+            let mut next = Some(range.start);
+            for cut in other.ranges[j..].iter().take_while(|cut| cut.start <= range.end) {
+                let Some(start) = next else { break };
+                if cut.start > start {
+                    out.push_sorted(FreshRange::new(start, cut.start - 1));
+                }
+                next = cut.end.checked_add(1).map(|after| after.max(start));
+            }
+            if let Some(start) = next.filter(|&start| start <= range.end) {
+                out.push_sorted(FreshRange::new(start, range.end));
+            }
+        }
+        out
+    }
+
+    fn symmetric_difference(&self, other: &FreshRanges) -> FreshRanges {
+        self.difference(other).union(&other.difference(self))
+    }
+
+    // The IDs from min to max, both included, that are not in any range. Nothing is left when min
+    // is above max.
+    fn complement(&self, min: u64, max: u64) -> FreshRanges {
+        if min > max {
+            return FreshRanges::new(self.merge_adjacent);
+        }
+        FreshRanges::from_ranges([FreshRange::new(min, max)], self.merge_adjacent).difference(self)
+    }
+
+    // The set without the IDs in the given range, such as a recall of spoiled IDs.
+    fn remove_range(&self, range: &FreshRange) -> FreshRanges {
+        self.difference(&[range.clone()].into_iter().collect())
+    }
+
+    // The number of fresh IDs, which is 2^64 if every u64 is fresh.
+    fn size(&self) -> u128 {
+        self.ranges.iter().map(FreshRange::size).sum()
+    }
+
+    // The original lookup, which scans every range for the value.
+    #[cfg(test)]
+    fn check_fresh_linear(&self, value: u64) -> bool {
         if value < self.min || value > self.max {
             return false;
         }
@@ -88,13 +279,37 @@ impl FreshRanges {
     }
 }
 
+// Collecting ranges builds the merged set with from_ranges, and never merges ranges that only
+// touch, as the puzzle doesn't. Use from_ranges to merge those as well.
+impl FromIterator<FreshRange> for FreshRanges {
+    fn from_iter<I: IntoIterator<Item = FreshRange>>(ranges: I) -> Self {
+        FreshRanges::from_ranges(ranges, false)
+    }
+}
+
+// Returns None for a half-open range with nothing in it, such as 5-5.
+fn parse_range(line: &str, bounds: Bounds) -> Option<FreshRange> {
+    let parts: Vec<&str> = line.split('-').collect();
+    if parts.len() != 2 {
+        panic!("Line ({}) did not match expected format", line);
+    }
+    let start = parts[0].parse::<u64>().expect("Could not parse start of range");
+    let end = parts[1].parse::<u64>().expect("Could not parse end of range");
+    match bounds {
+        Bounds::Inclusive if start <= end => Some(FreshRange::new(start, end)),
+        Bounds::HalfOpen if start < end => Some(FreshRange::new(start, end - 1)),
+        Bounds::HalfOpen if start == end => None,
+        _ => panic!("Line ({}) ends before it starts", line),
+    }
+}
 
-fn parse_file(filename: &str) -> (i32, i64) {
+// Reads the fresh ranges and the IDs to check from an input file.
+fn read_file(filename: &str, bounds: Bounds, merge_adjacent: bool) -> (FreshRanges, Vec<u64>) {
     let content = std::fs::read_to_string(filename).expect("Could not read file");
-    let mut fresh_ranges = FreshRanges::new();
+    let mut ranges: Vec<FreshRange> = vec![];
 
     // Get fresh ranges
-    let mut count = 0;
+    let mut values: Vec<u64> = vec![];
     let mut getting_ranges = true;
     for line in content.lines() {
         if line.trim().is_empty() {
@@ -102,28 +317,85 @@ fn parse_file(filename: &str) -> (i32, i64) {
             continue;
         }
         if getting_ranges {
-            let parts: Vec<&str> = line.split('-').collect();
-            if parts.len() != 2 {
-                panic!("Line ({}) did not match expected format", line);
-            }
-            let start = parts[0].parse::<u64>().expect("Could not parse start of range");
-            let end = parts[1].parse::<u64>().expect("Could not parse end of range");
-            fresh_ranges.add_range(FreshRange::new(start, end));
+            ranges.extend(parse_range(line, bounds));
         } else {
-            let value = line.parse::<u64>().expect("Could not parse value");
-            if fresh_ranges.check_fresh(value) {
-                count += 1;
-            }
+            values.push(line.parse::<u64>().expect("Could not parse value"));
         }
     }
+    (FreshRanges::from_ranges(ranges, merge_adjacent), values)
+}
+
+fn solve(fresh_ranges: &FreshRanges, values: &[u64]) -> (i32, u128) {
+    let count = fresh_ranges.check_fresh_all(values).iter().filter(|&&fresh| fresh).count() as i32;
+    (count, fresh_ranges.size())
+}
 
-    // Get number of fresh values
-    let mut total_fresh: i64 = 0;
+fn print_ranges(title: &str, fresh_ranges: &FreshRanges) {
+    println!("{} ({} ranges):", title, fresh_ranges.ranges.len());
     for range in &fresh_ranges.ranges {
-        println!("Fresh range: {}-{}", range.start, range.end);
-        total_fresh += (range.end - range.start + 1) as i64;
+        println!("  {}", range);
+    }
+}
+
+fn main() {
+    // "--add <start>-<end>" adds a range of fresh IDs to the input's ranges, and may be given
+    // several times.
+    // "--recall <start>-<end>" takes a range of spoiled IDs out of the input's ranges, and may be
+    // given several times.
+    // "--gaps" lists the IDs between the lowest and highest fresh ID that are not fresh.
+    // "--compare <file>" lists the fresh ranges the input shares with another file's, and those
+    // that only one of them has.
+    // "--bounds inclusive|half-open" sets how every range is read, including those given to
+    // --add and --recall; ranges are inclusive by default.
+    // "--merge-adjacent" also merges ranges that touch without overlapping.
+    let mut args = std::env::args().skip(1);
+    let mut adds: Vec<String> = vec![];
+    let mut recalls: Vec<String> = vec![];
+    let mut gaps = false;
+    let mut compare: Option<String> = None;
+    let mut bounds = Bounds::Inclusive;
+    let mut merge_adjacent = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--add" => adds.push(args.next().expect("Expected a range after --add")),
+            "--recall" => recalls.push(args.next().expect("Expected a range after --recall")),
+            "--gaps" => gaps = true,
+            "--compare" => compare = Some(args.next().expect("Expected a file after --compare")),
+            "--bounds" => {
+                let value = args.next().expect("Expected bounds after --bounds");
+                bounds = value.parse().expect("Could not parse bounds");
+            }
+            "--merge-adjacent" => merge_adjacent = true,
+            _ => panic!("Unknown argument ({})", arg),
+        }
     }
-    (count, total_fresh)
+
+    let (mut fresh_ranges, values) = read_file("inputs/input.txt", bounds, merge_adjacent);
+    for range in adds.iter().filter_map(|range| parse_range(range, bounds)) {
+        fresh_ranges.add_range(range);
+    }
+    for range in recalls.iter().filter_map(|range| parse_range(range, bounds)) {
+        fresh_ranges = fresh_ranges.remove_range(&range);
+    }
+
+    if gaps {
+        print_ranges("Spoiled", &fresh_ranges.complement(fresh_ranges.min, fresh_ranges.max));
+        return;
+    }
+
+    if let Some(filename) = compare {
+        let (other, _) = read_file(&filename, bounds, merge_adjacent);
+        print_ranges("In both", &fresh_ranges.intersection(&other));
+        print_ranges("Only in the input", &fresh_ranges.difference(&other));
+        print_ranges(&format!("Only in {}", filename), &other.difference(&fresh_ranges));
+        print_ranges("In exactly one", &fresh_ranges.symmetric_difference(&other));
+        print_ranges("In either", &fresh_ranges.union(&other));
+        return;
+    }
+
+    let (part_1_count, part_2_count) = solve(&fresh_ranges, &values);
+    println!("Number of fresh values: {}", part_1_count);
+    println!("Total number of fresh values: {}", part_2_count);
 }
 
 #[cfg(test)]
@@ -142,21 +414,251 @@ mod tests {
             (FreshRange::new(57, 100), FreshRange::new(30, 35), FreshRange::new(57, 100), false),
         ];
         for (mut range1, range2, expected_range, expected_result) in test_cases {
-            let result = range1.merge(&range2);
+            let result = range1.merge(&range2, false);
             assert_eq!(result, expected_result);
             assert_eq!(range1.start, expected_range.start);
             assert_eq!(range1.end, expected_range.end);
         }   
+
+        // Touching ranges are only merged when asked to, from either side.
+        let mut range = FreshRange::new(3, 5);
+        assert!(!range.merge(&FreshRange::new(6, 8), false));
+        assert!(range.merge(&FreshRange::new(6, 8), true));
+        assert!(range.merge(&FreshRange::new(0, 2), true));
+        assert!(!range.merge(&FreshRange::new(10, 12), true));
+        assert_eq!(range, FreshRange::new(0, 8));
+        let mut top = FreshRange::new(u64::MAX, u64::MAX);
+        assert!(!top.merge(&FreshRange::new(0, 0), true));
+        assert!(top.merge(&FreshRange::new(5, u64::MAX - 1), true));
+        assert_eq!(top, FreshRange::new(5, u64::MAX));
     }
 
     #[test]
     fn test_example() {
-        assert_eq!(parse_file("inputs/example.txt"), (3, 14));
+        let (mut fresh_ranges, values) = read_file("inputs/example.txt", Bounds::Inclusive, false);
+        assert_eq!(solve(&fresh_ranges, &values), (3, 14));
+        // 6-9 joins 3-5 and 10-20 into a single range, and makes ID 8 fresh.
+        fresh_ranges.add_range(FreshRange::new(6, 9));
+        assert_eq!(solve(&fresh_ranges, &values), (4, 18));
+
+        // Read half-open, every range loses its last ID: 3-4 no longer holds ID 5, and the rest
+        // merge into 10-19.
+        let (fresh_ranges, values) = read_file("inputs/example.txt", Bounds::HalfOpen, false);
+        assert_eq!(solve(&fresh_ranges, &values), (2, 12));
     }
-}
 
-fn main() {
-    let (part_1_count, part_2_count) = parse_file("inputs/input.txt");
-    println!("Number of fresh values: {}", part_1_count);
-    println!("Total number of fresh values: {}", part_2_count);
+    #[test]
+    fn test_bounds_and_adjacency() {
+        assert_eq!(parse_range("3-5", Bounds::Inclusive), Some(FreshRange::new(3, 5)));
+        assert_eq!(parse_range("3-5", Bounds::HalfOpen), Some(FreshRange::new(3, 4)));
+        assert_eq!(parse_range("5-5", Bounds::HalfOpen), None);
+        assert_eq!(parse_range("0-18446744073709551615", Bounds::Inclusive).unwrap().size(), 1 << 64);
+        assert_eq!("half-open".parse::<Bounds>(), Ok(Bounds::HalfOpen));
+        assert!("open".parse::<Bounds>().is_err());
+
+        let list = ranges(&[(3, 5), (6, 8), (10, 12), (13, 13), (20, 25)]);
+        assert_eq!(list.ranges.len(), 5);
+        let merged = FreshRanges::from_ranges(list.ranges.clone(), true);
+        assert_eq!(merged.ranges, ranges(&[(3, 8), (10, 13), (20, 25)]).ranges);
+        assert_eq!((list.size(), merged.size()), (16, 16));
+
+        let mut incremental = FreshRanges::new(true);
+        for range in [(20, 25), (6, 8), (13, 13), (3, 5), (10, 12), (9, 9)] {
+            incremental.add_range(FreshRange::new(range.0, range.1));
+        }
+        assert_eq!(incremental.ranges, vec![FreshRange::new(3, 13), FreshRange::new(20, 25)]);
+
+        // Sizes are counted without overflowing, even when every u64 is fresh.
+        let everything = FreshRanges::from_ranges(ranges(&[(0, 9), (10, u64::MAX)]).ranges, true);
+        assert_eq!(everything.ranges.len(), 1);
+        assert_eq!(everything.size(), u64::MAX as u128 + 1);
+        assert_eq!(ranges(&[(0, u64::MAX - 1), (u64::MAX, u64::MAX)]).size(), 1 << 64);
+
+        // Merging touching ranges changes how a set is stored, not which IDs it holds, and the set
+        // operations keep them merged.
+        for seed in 0..200 {
+            let list = random_ranges(6, 50, seed);
+            let a = FreshRanges::from_ranges(list.clone(), true);
+            let b = FreshRanges::from_ranges(random_ranges(5, 50, seed + 1000), true);
+            let (ma, mb) = (mask(&a), mask(&b));
+            assert_eq!(ma, mask(&list.into_iter().collect()), "seed {}", seed);
+            let results = [
+                (a.union(&b), ma | mb),
+                (a.intersection(&b), ma & mb),
+                (a.difference(&b), ma & !mb),
+                (a.symmetric_difference(&b), ma ^ mb),
+            ];
+            for (i, (result, expected)) in results.iter().enumerate() {
+                assert!(result.merge_adjacent);
+                assert_normalized(result);
+                assert_eq!(mask(result), *expected, "seed {} operation {}", seed, i);
+            }
+        }
+    }
+
+    // A reproducible list of ranges, dense enough that many of them overlap.
+    fn random_ranges(count: usize, span: u64, seed: u64) -> Vec<FreshRange> {
+        let mut state = seed;
+        let mut next = || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            state >> 16
+        };
+        (0..count)
+            .map(|_| {
+                let start = next() % span;
+                FreshRange::new(start, start + next() % (span / count as u64 + 1))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_bulk_matches_incremental() {
+        let (input, _) = read_file("inputs/input.txt", Bounds::Inclusive, false);
+        let mut lists = vec![vec![], vec![FreshRange::new(7, 7)], input.ranges];
+        lists.extend((0..30).map(|seed| random_ranges(50, 1000, seed)));
+        let content = std::fs::read_to_string("inputs/input.txt").unwrap();
+        let (range_lines, _) = content.split_once("\n\n").unwrap();
+        lists.push(range_lines.lines().filter_map(|line| parse_range(line, Bounds::Inclusive)).collect());
+        for list in lists {
+            let mut incremental = FreshRanges::new(false);
+            for range in &list {
+                incremental.add_range(range.clone());
+            }
+            let bulk: FreshRanges = list.into_iter().collect();
+            assert_eq!(bulk, incremental);
+            assert!(bulk.ranges.windows(2).all(|pair| pair[0].end < pair[1].start));
+        }
+    }
+
+    // The IDs below 64 in a set, as a bit mask.
+    fn mask(fresh_ranges: &FreshRanges) -> u64 {
+        (0..64).filter(|&id| fresh_ranges.check_fresh(id)).fold(0, |mask, id| mask | 1 << id)
+    }
+
+    // Sorted, with no overlaps (nor touching ranges, if those are merged), and with min and max
+    // matching the ranges.
+    fn assert_normalized(fresh_ranges: &FreshRanges) {
+        let gap = if fresh_ranges.merge_adjacent { 2 } else { 1 };
+        assert!(fresh_ranges.ranges.iter().all(|range| range.start <= range.end));
+        assert!(fresh_ranges.ranges.windows(2).all(|pair| pair[1].start - pair[0].end >= gap));
+        if let (Some(first), Some(last)) = (fresh_ranges.ranges.first(), fresh_ranges.ranges.last()) {
+            assert_eq!((fresh_ranges.min, fresh_ranges.max), (first.start, last.end));
+        }
+    }
+
+    #[test]
+    fn test_set_algebra() {
+        let a = ranges(&[(3, 5), (10, 14), (16, 20), (30, 40)]);
+        let b = ranges(&[(0, 3), (12, 17), (25, 35), (40, 40)]);
+        let bounds = |fresh_ranges: FreshRanges| {
+            fresh_ranges.ranges.iter().map(|range| (range.start, range.end)).collect::<Vec<_>>()
+        };
+        assert_eq!(bounds(a.intersection(&b)), vec![(3, 3), (12, 14), (16, 17), (30, 35), (40, 40)]);
+        assert_eq!(bounds(a.difference(&b)), vec![(4, 5), (10, 11), (18, 20), (36, 39)]);
+        assert_eq!(bounds(b.difference(&a)), vec![(0, 2), (15, 15), (25, 29)]);
+        assert_eq!(
+            bounds(a.symmetric_difference(&b)),
+            vec![(0, 2), (4, 5), (10, 11), (15, 15), (18, 20), (25, 29), (36, 39)]
+        );
+        assert_eq!(bounds(a.union(&b)), vec![(0, 5), (10, 20), (25, 40)]);
+        assert_eq!(bounds(a.complement(a.min, a.max)), vec![(6, 9), (15, 15), (21, 29)]);
+        // The bounds may lie outside the set, or inside a range or a gap.
+        assert_eq!(bounds(a.complement(0, 50)), vec![(0, 2), (6, 9), (15, 15), (21, 29), (41, 50)]);
+        assert_eq!(bounds(a.complement(12, 32)), vec![(15, 15), (21, 29)]);
+        assert_eq!(bounds(a.complement(7, 8)), vec![(7, 8)]);
+        assert_eq!(bounds(a.complement(4, 4)), vec![]);
+        assert_eq!(bounds(a.complement(9, 3)), vec![]);
+        assert_eq!(bounds(FreshRanges::new(false).complement(0, 9)), vec![(0, 9)]);
+        assert_eq!(bounds(a.remove_range(&FreshRange::new(5, 12))), vec![(3, 4), (13, 14), (16, 20), (30, 40)]);
+        assert_eq!(bounds(a.remove_range(&FreshRange::new(0, 100))), vec![]);
+        let empty = FreshRanges::new(false);
+        assert_eq!(bounds(empty.complement(empty.min, empty.max)), vec![]);
+
+        // Ranges reaching u64::MAX don't overflow.
+        let top = ranges(&[(u64::MAX - 5, u64::MAX)]);
+        let recalled = FreshRange::new(u64::MAX - 3, u64::MAX);
+        assert_eq!(bounds(top.remove_range(&recalled)), vec![(u64::MAX - 5, u64::MAX - 4)]);
+        assert_eq!(bounds(top.remove_range(&FreshRange::new(u64::MAX - 3, u64::MAX - 3))).len(), 2);
+        assert_eq!(bounds(top.intersection(&ranges(&[(0, u64::MAX)]))), vec![(u64::MAX - 5, u64::MAX)]);
+        assert_eq!(bounds(top.complement(0, u64::MAX)), vec![(0, u64::MAX - 6)]);
+        assert_eq!(bounds(top.complement(u64::MAX, u64::MAX)), vec![]);
+        assert_eq!(bounds(ranges(&[(0, u64::MAX)]).complement(0, u64::MAX)), vec![]);
+        assert_eq!(bounds(ranges(&[(0, 5)]).complement(0, u64::MAX)), vec![(6, u64::MAX)]);
+    }
+
+    #[test]
+    fn test_set_algebra_matches_masks() {
+        for seed in 0..200 {
+            let a: FreshRanges = random_ranges(6, 50, seed).into_iter().collect();
+            let b: FreshRanges = random_ranges(5, 50, seed + 1000).into_iter().collect();
+            let (ma, mb) = (mask(&a), mask(&b));
+            let span = if a.ranges.is_empty() { 0 } else { (u64::MAX >> (63 - a.max)) & !((1 << a.min) - 1) };
+            let recalled = FreshRange::new(seed % 50, seed % 50 + seed % 7);
+            let recalled_mask = (u64::MAX >> (63 - recalled.end)) & !((1 << recalled.start) - 1);
+            // A window that may start before the set's first range and end after its last.
+            let (low, high) = (seed % 20, 30 + seed % 34);
+            let window = (u64::MAX >> (63 - high)) & !((1 << low) - 1);
+            let results = [
+                (a.union(&b), ma | mb),
+                (a.intersection(&b), ma & mb),
+                (a.difference(&b), ma & !mb),
+                (b.difference(&a), mb & !ma),
+                (a.symmetric_difference(&b), ma ^ mb),
+                (a.complement(a.min, a.max), span & !ma),
+                (a.complement(0, 63), !ma),
+                (a.complement(low, high), window & !ma),
+                (a.remove_range(&recalled), ma & !recalled_mask),
+            ];
+            for (i, (result, expected)) in results.iter().enumerate() {
+                assert_normalized(result);
+                assert_eq!(mask(result), *expected, "seed {} operation {}", seed, i);
+            }
+        }
+    }
+
+    fn ranges(bounds: &[(u64, u64)]) -> FreshRanges {
+        let mut fresh_ranges = FreshRanges::new(false);
+        for &(start, end) in bounds {
+            fresh_ranges.add_range(FreshRange::new(start, end));
+        }
+        fresh_ranges
+    }
+
+    #[test]
+    fn test_lookups() {
+        let fresh_ranges = ranges(&[(3, 5), (10, 14), (16, 20), (12, 18), (30, 30)]);
+        let values: Vec<u64> = (0..=35).chain([u64::MAX, 17, 4]).collect();
+        let fresh = fresh_ranges.check_fresh_all(&values);
+        for (&value, &is_fresh) in values.iter().zip(&fresh) {
+            assert_eq!(fresh_ranges.check_fresh(value), fresh_ranges.check_fresh_linear(value), "{}", value);
+            assert_eq!(is_fresh, fresh_ranges.check_fresh_linear(value), "{}", value);
+        }
+        assert_eq!(fresh.iter().filter(|&&f| f).count(), 3 + 11 + 1 + 2);
+        assert!(FreshRanges::new(false).check_fresh_all(&[0, 1]).iter().all(|&f| !f));
+        assert!(fresh_ranges.check_fresh_all(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_lookups_on_input() {
+        let content = std::fs::read_to_string("inputs/input.txt").unwrap();
+        let (range_lines, value_lines) = content.split_once("\n\n").unwrap();
+        let bounds: Vec<(u64, u64)> = range_lines
+            .lines()
+            .map(|line| {
+                let (start, end) = line.split_once('-').unwrap();
+                (start.parse().unwrap(), end.parse().unwrap())
+            })
+            .collect();
+        let fresh_ranges = ranges(&bounds);
+        let mut values: Vec<u64> = value_lines.lines().map(|line| line.parse().unwrap()).collect();
+        // Probe the edges of every range as well.
+        values.extend(bounds.iter().flat_map(|&(start, end)| [start - 1, start, end, end + 1]));
+        let fresh = fresh_ranges.check_fresh_all(&values);
+        for (&value, &is_fresh) in values.iter().zip(&fresh) {
+            assert_eq!(fresh_ranges.check_fresh(value), fresh_ranges.check_fresh_linear(value), "{}", value);
+            assert_eq!(is_fresh, fresh_ranges.check_fresh_linear(value), "{}", value);
+            // A single ID is looked up on its own rather than swept.
+            assert_eq!(fresh_ranges.check_fresh_all(&[value]), vec![is_fresh], "{}", value);
+        }
+    }
 }